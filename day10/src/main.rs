@@ -1,93 +1,685 @@
 use std::{
+    env,
+    fmt::{self, Display, Formatter, Write},
+    fs,
     io::{self, BufRead},
     ops::Deref,
+    str::FromStr,
 };
 
-#[derive(Debug, Copy, Clone)]
-enum Op {
-    Noop,
-    Addx(i32),
+/// The state of the CPU between instructions.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Cpu {
+    x: i32,
+    y: i32,
+    /// Index of the next instruction to execute.
+    pc: usize,
+    /// Number of cycles started so far; during the first cycle this is 1.
+    cycle: u32,
 }
 
-impl Op {
-    fn cycles(self) -> i32 {
-        match self {
-            Op::Noop => 1,
-            Op::Addx(_) => 2,
+/// Sees the CPU during every cycle, before the instruction being executed has taken effect.
+trait Observer {
+    fn cycle(&mut self, cpu: &Cpu);
+}
+
+impl<F: FnMut(&Cpu)> Observer for F {
+    fn cycle(&mut self, cpu: &Cpu) {
+        self(cpu)
+    }
+}
+
+impl Cpu {
+    fn new() -> Cpu {
+        Cpu {
+            x: 1,
+            y: 0,
+            pc: 0,
+            cycle: 0,
+        }
+    }
+
+    fn halted(&self, program: &[Instr]) -> bool {
+        self.pc >= program.len()
+    }
+
+    /// Executes the next instruction, if there is one.
+    fn step(&mut self, program: &[Instr], observer: &mut impl Observer) -> Result<bool, RunError> {
+        if self.halted(program) {
+            return Ok(false);
         }
+        let instr = &program[self.pc];
+        for _ in 0..instr.def.cycles {
+            self.cycle += 1;
+            observer.cycle(self);
+        }
+        self.pc += 1;
+        (instr.def.exec)(self, &instr.args).map_err(|message| RunError::Failed {
+            pc: self.pc - 1,
+            cycle: self.cycle,
+            message,
+        })?;
+        Ok(true)
     }
 
-    fn op(self, reg: &mut i32) {
+    /// Fails if the program is still running after `max_cycles` cycles.
+    fn check_budget(&self, program: &[Instr], max_cycles: u32) -> Result<(), RunError> {
+        if self.cycle >= max_cycles && !self.halted(program) {
+            return Err(RunError::OutOfCycles {
+                pc: self.pc,
+                cycle: self.cycle,
+            });
+        }
+        Ok(())
+    }
+
+    /// Runs the program to the end, or until it has used up `max_cycles` cycles, since
+    /// with `jmp` it may never end.
+    fn run(
+        &mut self,
+        program: &[Instr],
+        observer: &mut impl Observer,
+        max_cycles: u32,
+    ) -> Result<(), RunError> {
+        while self.step(program, observer)? {
+            self.check_budget(program, max_cycles)?;
+        }
+        Ok(())
+    }
+}
+
+/// More cycles than any program that terminates should need.
+const MAX_CYCLES: u32 = 1_000_000;
+
+/// Why a program stopped before its end.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum RunError {
+    /// The instruction at `pc` failed, for example because a register overflowed.
+    Failed {
+        pc: usize,
+        cycle: u32,
+        message: String,
+    },
+    OutOfCycles {
+        pc: usize,
+        cycle: u32,
+    },
+}
+
+impl Display for RunError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
         match self {
-            Op::Noop => (),
-            Op::Addx(c) => *reg += c,
+            RunError::Failed { pc, cycle, message } => {
+                write!(f, "instruction {pc} failed after cycle {cycle}: {message}")
+            }
+            RunError::OutOfCycles { pc, cycle } => write!(
+                f,
+                "still running at pc {pc} after {cycle} cycles, is it stuck in a loop?"
+            ),
         }
     }
 }
 
-type Parsed = Vec<Op>;
+/// How an instruction is written and what it does once its cycles are over.
+#[derive(Clone, Copy)]
+struct InstrDef {
+    name: &'static str,
+    arity: usize,
+    cycles: u32,
+    /// Called with the program counter already pointing past the instruction.
+    exec: fn(&mut Cpu, &[i32]) -> Result<(), String>,
+}
+
+impl fmt::Debug for InstrDef {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name)
+    }
+}
+
+impl PartialEq for InstrDef {
+    fn eq(&self, other: &InstrDef) -> bool {
+        self.name == other.name
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Registry {
+    defs: Vec<InstrDef>,
+}
+
+impl Registry {
+    fn new() -> Registry {
+        Registry { defs: vec![] }
+    }
+
+    /// Adds an instruction, replacing any previous one with the same name.
+    fn register(&mut self, def: InstrDef) -> &mut Registry {
+        self.defs.retain(|d| d.name != def.name);
+        self.defs.push(def);
+        self
+    }
+
+    fn get(&self, name: &str) -> Option<&InstrDef> {
+        self.defs.iter().find(|d| d.name == name)
+    }
+}
+
+impl Default for Registry {
+    /// The puzzle's `noop` and `addx`, plus `addy`, `mul` (of `X`) and `jmp`, which jumps
+    /// relative to itself.
+    fn default() -> Registry {
+        let mut registry = Registry::new();
+        registry
+            .register(InstrDef {
+                name: "noop",
+                arity: 0,
+                cycles: 1,
+                exec: |_, _| Ok(()),
+            })
+            .register(InstrDef {
+                name: "addx",
+                arity: 1,
+                cycles: 2,
+                exec: |cpu, args| {
+                    cpu.x = overflowed(cpu.x.checked_add(args[0]), "X")?;
+                    Ok(())
+                },
+            })
+            .register(InstrDef {
+                name: "addy",
+                arity: 1,
+                cycles: 2,
+                exec: |cpu, args| {
+                    cpu.y = overflowed(cpu.y.checked_add(args[0]), "Y")?;
+                    Ok(())
+                },
+            })
+            .register(InstrDef {
+                name: "mul",
+                arity: 1,
+                cycles: 4,
+                exec: |cpu, args| {
+                    cpu.x = overflowed(cpu.x.checked_mul(args[0]), "X")?;
+                    Ok(())
+                },
+            })
+            .register(InstrDef {
+                name: "jmp",
+                arity: 1,
+                cycles: 1,
+                exec: |cpu, args| {
+                    // Out of range targets just halt the program.
+                    cpu.pc = (cpu.pc as i64 - 1 + args[0] as i64)
+                        .try_into()
+                        .unwrap_or(usize::MAX);
+                    Ok(())
+                },
+            });
+        registry
+    }
+}
+
+/// The result of checked arithmetic on register `reg`, or an error if it overflowed.
+fn overflowed(value: Option<i32>, reg: &str) -> Result<i32, String> {
+    value.ok_or_else(|| format!("{reg} overflowed"))
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Instr {
+    def: InstrDef,
+    args: Vec<i32>,
+}
+
+impl Display for Instr {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str(self.def.name)?;
+        for arg in &self.args {
+            write!(f, " {arg}")?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct ParseError {
+    line: usize,
+    message: String,
+}
+
+impl Display for ParseError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+type Parsed = Vec<Instr>;
 
 fn main() {
     let stdin = io::stdin();
     let lines = stdin.lock().lines().map(|x| x.unwrap());
-    let map = parse(lines);
-    println!("part1: {}", part1(&map));
-    println!("part2: \n{}", part2(&map));
+    let map = parse(lines, &Registry::default()).unwrap_or_else(|e| panic!("{e}"));
+
+    let mut args = env::args().skip(1);
+    match args.next().as_deref() {
+        None => {
+            println!("part1: {}", part1(&map).unwrap_or_else(|e| panic!("{e}")));
+            let screen = part2(&map).unwrap_or_else(|e| panic!("{e}"));
+            match ocr(&screen) {
+                Ok(text) => println!("part2: {text}"),
+                Err(e) => {
+                    eprintln!("{e}");
+                    println!("part2: \n{screen}");
+                }
+            }
+        }
+        Some("fmt") => print!("{}", emit(&map)),
+        Some("list") => print!("{}", listing(&map)),
+        Some("debug") => {
+            let mut debugger = Debugger::default();
+            let mut trace_file = None;
+            while let Some(flag) = args.next() {
+                let value = args
+                    .next()
+                    .unwrap_or_else(|| panic!("{flag} needs a value"));
+                match flag.as_str() {
+                    "--break" => debugger.breakpoints.push(value.parse().unwrap()),
+                    "--watch" => debugger.watches.push(value.parse().unwrap()),
+                    "--trace" => trace_file = Some(value),
+                    "--max-cycles" => debugger.max_cycles = value.parse().unwrap(),
+                    _ => panic!("Unknown option: {flag}"),
+                }
+            }
+            let (stops, trace) = debugger.run(&map);
+            print!("{stops}");
+            if let Some(path) = trace_file {
+                fs::write(path, trace).unwrap();
+            }
+        }
+        Some(other) => panic!("Unknown command: {other}"),
+    }
 }
 
-fn parse(lines: impl Iterator<Item = impl Deref<Target = str>>) -> Parsed {
+fn parse(
+    lines: impl Iterator<Item = impl Deref<Target = str>>,
+    registry: &Registry,
+) -> Result<Parsed, ParseError> {
     let mut result = Parsed::new();
-    for line in lines {
-        let line: &str = line.trim();
-        if line == "noop" {
-            result.push(Op::Noop);
-        } else if let Some(rest) = line.strip_prefix("addx ") {
-            result.push(Op::Addx(rest.parse::<i32>().unwrap()))
-        } else {
-            panic!("Unexpected: {line}");
+    for (i, line) in lines.enumerate() {
+        let error = |message| ParseError {
+            line: i + 1,
+            message,
+        };
+        let mut words = line.split_whitespace();
+        let Some(name) = words.next() else {
+            continue;
+        };
+        let def = *registry
+            .get(name)
+            .ok_or_else(|| error(format!("unknown instruction {name:?}")))?;
+        let args = words
+            .map(|w| w.parse().map_err(|_| error(format!("bad argument {w:?}"))))
+            .collect::<Result<Vec<_>, _>>()?;
+        if args.len() != def.arity {
+            return Err(error(format!(
+                "{name} takes {} argument(s), got {}",
+                def.arity,
+                args.len()
+            )));
         }
+        result.push(Instr { def, args });
     }
-    result
+    Ok(result)
 }
 
-fn part1(parsed: &Parsed) -> i32 {
-    let interesting = [20, 60, 100, 140, 180, 220];
-    let mut cycle = 0;
-    let mut reg = 1;
-    let mut signal = 0;
-    for op in parsed {
-        for _ in 0..op.cycles() {
-            cycle += 1;
-            if interesting.contains(&cycle) {
-                eprintln!("{cycle}: {reg} = {}", cycle * reg);
-                signal += cycle * reg;
-            }
+/// Sums `cycle * X` over the given cycles.
+struct SignalStrength {
+    cycles: Vec<u32>,
+    total: i64,
+}
+
+impl Observer for SignalStrength {
+    fn cycle(&mut self, cpu: &Cpu) {
+        if self.cycles.contains(&cpu.cycle) {
+            let strength = cpu.cycle as i64 * cpu.x as i64;
+            eprintln!("{}: {} = {strength}", cpu.cycle, cpu.x);
+            self.total += strength;
         }
-        op.op(&mut reg);
     }
-    signal
 }
 
-fn part2(parsed: &Parsed) -> String {
-    let mut cycle = 0;
-    let mut reg = 1;
+/// Draws a pixel every cycle, lit when the three pixel wide sprite centered on `X` covers it.
+struct Crt {
+    width: u32,
+    screen: String,
+}
+
+impl Observer for Crt {
+    fn cycle(&mut self, cpu: &Cpu) {
+        let column = (cpu.cycle - 1) % self.width;
+        let lit = (column as i32 - cpu.x).abs() <= 1;
+        self.screen.push(if lit { '#' } else { '.' });
+        if column == self.width - 1 {
+            self.screen.push('\n');
+        }
+    }
+}
+
+fn part1(parsed: &Parsed) -> Result<i64, RunError> {
+    let mut signal = SignalStrength {
+        cycles: (20..=220).step_by(40).collect(),
+        total: 0,
+    };
+    Cpu::new().run(parsed, &mut signal, MAX_CYCLES)?;
+    Ok(signal.total)
+}
+
+fn part2(parsed: &Parsed) -> Result<String, RunError> {
+    let mut crt = Crt {
+        width: 40,
+        screen: String::new(),
+    };
+    Cpu::new().run(parsed, &mut crt, MAX_CYCLES)?;
+    Ok(crt.screen)
+}
+
+/// The letters of the font drawn on the CRT, each four pixels wide and six high.
+static GLYPHS: &[(char, &str)] = &[
+    ('A', ".##.#..##..######..##..#"),
+    ('B', "###.#..####.#..##..####."),
+    ('C', ".##.#..##...#...#..#.##."),
+    ('E', "#####...###.#...#...####"),
+    ('F', "#####...###.#...#...#..."),
+    ('G', ".##.#..##...#.###..#.###"),
+    ('H', "#..##..######..##..##..#"),
+    ('I', ".###..#...#...#...#..###"),
+    ('J', "..##...#...#...##..#.##."),
+    ('K', "#..##.#.##..#.#.#.#.#..#"),
+    ('L', "#...#...#...#...#...####"),
+    ('O', ".##.#..##..##..##..#.##."),
+    ('P', "###.#..##..####.#...#..."),
+    ('R', "###.#..##..####.#.#.#..#"),
+    ('S', ".####...#....##....####."),
+    ('U', "#..##..##..##..##..#.##."),
+    ('Z', "####...#..#..#..#...####"),
+];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct UnknownGlyph {
+    /// Which letter on the screen, counting from 0.
+    position: usize,
+    /// Its pixels, one row per line, including the column separating it from the next one.
+    glyph: String,
+}
+
+impl Display for UnknownGlyph {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        writeln!(f, "unknown glyph at letter {}:", self.position)?;
+        f.write_str(&self.glyph)
+    }
+}
+
+/// Reads the letters drawn on a screen rendered by [`Crt`], each followed by a blank column.
+fn ocr(screen: &str) -> Result<String, UnknownGlyph> {
+    let rows = screen.lines().map(str::as_bytes).collect::<Vec<_>>();
+    let width = rows.iter().map(|row| row.len()).max().unwrap_or(0);
+    (0..width.div_ceil(5))
+        .map(|position| {
+            let columns = position * 5..position * 5 + 5;
+            let cells = rows
+                .iter()
+                .map(|row| {
+                    columns
+                        .clone()
+                        .map(|c| *row.get(c).unwrap_or(&b'.') as char)
+                        .collect::<String>()
+                })
+                .collect::<Vec<_>>();
+            let blank_gap = cells.iter().all(|row| row.ends_with('.'));
+            let pixels = cells.iter().map(|row| &row[..4]).collect::<String>();
+            GLYPHS
+                .iter()
+                .find(|&&(_, glyph)| blank_gap && glyph == pixels)
+                .map(|&(c, _)| c)
+                .ok_or_else(|| UnknownGlyph {
+                    position,
+                    glyph: cells.iter().map(|row| format!("{row}\n")).collect(),
+                })
+        })
+        .collect()
+}
+
+/// The program as source that parses back to the same instructions.
+fn emit(program: &[Instr]) -> String {
+    program.iter().map(|instr| format!("{instr}\n")).collect()
+}
+
+/// The program with instruction indices and the cycles each one would occupy if the
+/// program ran straight through without jumping.
+fn listing(program: &[Instr]) -> String {
     let mut result = String::new();
-    for op in parsed {
-        for _ in 0..op.cycles() {
-            if (cycle % 40i32 - reg).abs() <= 1 {
-                result.push('#');
-            } else {
-                result.push('.');
-            }
-            cycle += 1;
+    let mut cycle = 0;
+    for (i, instr) in program.iter().enumerate() {
+        let cycles = match instr.def.cycles {
+            1 => format!("{}", cycle + 1),
+            n => format!("{}-{}", cycle + 1, cycle + n),
+        };
+        writeln!(result, "{i:>4}  {:<12}; cycle {cycles}", instr.to_string()).unwrap();
+        cycle += instr.def.cycles;
+    }
+    result
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Reg {
+    X,
+    Y,
+}
 
-            if (cycle % 40) == 0 {
-                result.push('\n');
+impl Reg {
+    fn get(self, cpu: &Cpu) -> i32 {
+        match self {
+            Reg::X => cpu.x,
+            Reg::Y => cpu.y,
+        }
+    }
+}
+
+impl FromStr for Reg {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Reg, String> {
+        match s {
+            "x" | "X" => Ok(Reg::X),
+            "y" | "Y" => Ok(Reg::Y),
+            _ => Err(format!("no register {s:?}")),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Breakpoint {
+    /// Stops during the given cycle, written `cycle:N`.
+    Cycle(u32),
+    /// Stops before executing the instruction at the given index, written `instr:N`.
+    Instr(usize),
+}
+
+impl FromStr for Breakpoint {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Breakpoint, String> {
+        let bad = || format!("bad breakpoint {s:?}, expected cycle:N or instr:N");
+        match s.split_once(':').ok_or_else(bad)? {
+            ("cycle", n) => n.parse().map(Breakpoint::Cycle).map_err(|_| bad()),
+            ("instr", n) => n.parse().map(Breakpoint::Instr).map_err(|_| bad()),
+            _ => Err(bad()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Watch {
+    /// Stops after an instruction changes the register, written `x`.
+    Changes(Reg),
+    /// Stops after an instruction sets the register to the value, written `x=N`.
+    Equals(Reg, i32),
+}
+
+impl Watch {
+    fn reg(self) -> Reg {
+        match self {
+            Watch::Changes(reg) | Watch::Equals(reg, _) => reg,
+        }
+    }
+
+    fn triggered(self, before: &Cpu, after: &Cpu) -> bool {
+        match self {
+            Watch::Changes(reg) => reg.get(before) != reg.get(after),
+            Watch::Equals(reg, value) => reg.get(before) != value && reg.get(after) == value,
+        }
+    }
+}
+
+impl Display for Watch {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Watch::Changes(reg) => write!(f, "{reg:?}"),
+            Watch::Equals(reg, value) => write!(f, "{reg:?}={value}"),
+        }
+    }
+}
+
+impl FromStr for Watch {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Watch, String> {
+        match s.split_once('=') {
+            None => Ok(Watch::Changes(s.parse()?)),
+            Some((reg, value)) => Ok(Watch::Equals(
+                reg.parse()?,
+                value
+                    .parse()
+                    .map_err(|_| format!("bad value in watch {s:?}"))?,
+            )),
+        }
+    }
+}
+
+/// Runs a program, reporting the state of the CPU and the CRT wherever it is told to
+/// stop. With no breakpoints or watches it stops before every instruction.
+#[derive(Debug, Clone)]
+struct Debugger {
+    breakpoints: Vec<Breakpoint>,
+    watches: Vec<Watch>,
+    /// Gives up on programs that run longer than this, with a last stop saying so.
+    max_cycles: u32,
+}
+
+impl Default for Debugger {
+    fn default() -> Debugger {
+        Debugger {
+            breakpoints: vec![],
+            watches: vec![],
+            max_cycles: MAX_CYCLES,
+        }
+    }
+}
+
+/// The per-cycle half of a debugger run.
+struct Session<'a> {
+    debugger: &'a Debugger,
+    program: &'a [Instr],
+    crt: Crt,
+    stops: String,
+    trace: String,
+}
+
+impl Session<'_> {
+    fn stop(&mut self, cpu: &Cpu, when: &str, reason: &str) {
+        let instr = match self.program.get(cpu.pc) {
+            Some(instr) => format!("pc {} `{instr}`", cpu.pc),
+            None => "halted".to_owned(),
+        };
+        let sprite = (0..self.crt.width as i32)
+            .map(|column| {
+                if (column - cpu.x).abs() <= 1 {
+                    '#'
+                } else {
+                    '.'
+                }
+            })
+            .collect::<String>();
+        // The row being drawn, or the one just finished if the next hasn't started yet.
+        let row = self.crt.screen.lines().last().unwrap_or("");
+        writeln!(self.stops, "== {when}, {instr}: {reason}").unwrap();
+        writeln!(self.stops, "X={} Y={}", cpu.x, cpu.y).unwrap();
+        writeln!(self.stops, "sprite {sprite}").unwrap();
+        writeln!(self.stops, "crt    {row}").unwrap();
+    }
+}
+
+impl Observer for Session<'_> {
+    fn cycle(&mut self, cpu: &Cpu) {
+        let instr = self.program[cpu.pc].to_string();
+        writeln!(
+            self.trace,
+            "{:>5} {:>4}  {instr:<12} X={} Y={}",
+            cpu.cycle, cpu.pc, cpu.x, cpu.y
+        )
+        .unwrap();
+        self.crt.cycle(cpu);
+        if self
+            .debugger
+            .breakpoints
+            .contains(&Breakpoint::Cycle(cpu.cycle))
+        {
+            let when = format!("during cycle {}", cpu.cycle);
+            self.stop(cpu, &when, &format!("breakpoint cycle:{}", cpu.cycle));
+        }
+    }
+}
+
+impl Debugger {
+    /// Runs the program to the end, or until an instruction fails or it runs out of cycles,
+    /// and returns the reports of all stops and a trace of every cycle.
+    fn run(&self, program: &[Instr]) -> (String, String) {
+        let step_mode = self.breakpoints.is_empty() && self.watches.is_empty();
+        let mut session = Session {
+            debugger: self,
+            program,
+            crt: Crt {
+                width: 40,
+                screen: String::new(),
+            },
+            stops: String::new(),
+            trace: String::new(),
+        };
+        let mut cpu = Cpu::new();
+        while !cpu.halted(program) {
+            let when = format!("before cycle {}", cpu.cycle + 1);
+            if step_mode {
+                session.stop(&cpu, &when, "step");
+            } else if self.breakpoints.contains(&Breakpoint::Instr(cpu.pc)) {
+                session.stop(&cpu, &when, &format!("breakpoint instr:{}", cpu.pc));
+            }
+            let before = cpu.clone();
+            let stepped = cpu.step(program, &mut session);
+            for watch in &self.watches {
+                if watch.triggered(&before, &cpu) {
+                    let reg = watch.reg();
+                    let (from, to) = (reg.get(&before), reg.get(&cpu));
+                    let reason = format!("watch {watch}: {reg:?} {from} -> {to}");
+                    session.stop(&cpu, &format!("after cycle {}", cpu.cycle), &reason);
+                }
+            }
+            if let Err(e) = stepped.and_then(|_| cpu.check_budget(program, self.max_cycles)) {
+                let when = format!("after cycle {}", cpu.cycle);
+                session.stop(&cpu, &when, &e.to_string());
+                break;
             }
         }
-        op.op(&mut reg);
+        (session.stops, session.trace)
     }
-    result
 }
 
 #[cfg(test)]
@@ -248,15 +840,15 @@ noop";
 
     #[test]
     fn test_part1() {
-        let root = parse(sample());
-        assert_eq!(part1(&root), 13140);
+        let root = parse(sample(), &Registry::default()).unwrap();
+        assert_eq!(part1(&root), Ok(13140));
     }
 
     #[test]
     fn test_part2() {
-        let root = parse(sample());
+        let root = parse(sample(), &Registry::default()).unwrap();
         assert_eq!(
-            part2(&root),
+            part2(&root).unwrap(),
             "\
 ##..##..##..##..##..##..##..##..##..##..
 ###...###...###...###...###...###...###.
@@ -266,4 +858,246 @@ noop";
 #######.......#######.......#######.....\n"
         );
     }
+
+    #[test]
+    fn test_parse_errors() {
+        let registry = Registry::default();
+        let error = |lines: &[&str]| parse(lines.iter().copied(), &registry).unwrap_err();
+        assert_eq!(
+            error(&["noop", "", "addz 3"]).to_string(),
+            "line 3: unknown instruction \"addz\""
+        );
+        assert_eq!(error(&["addx x"]).to_string(), "line 1: bad argument \"x\"");
+        assert_eq!(
+            error(&["noop", "addx"]).to_string(),
+            "line 2: addx takes 1 argument(s), got 0"
+        );
+        assert!(parse(["noop 1"].into_iter(), &Registry::new()).is_err());
+    }
+
+    #[test]
+    fn test_instructions() {
+        let registry = Registry::default();
+        let program = parse(
+            ["addx 2", "mul 5", "jmp 2", "addx 100", "addy -4", "jmp -10"].into_iter(),
+            &registry,
+        )
+        .unwrap();
+        let mut cpu = Cpu::new();
+        let mut seen = vec![];
+        let result = cpu.run(
+            &program,
+            &mut |cpu: &Cpu| seen.push((cpu.cycle, cpu.x)),
+            100,
+        );
+        assert_eq!(result, Ok(()));
+        assert_eq!((cpu.x, cpu.y, cpu.cycle), (15, -4, 10));
+        assert!(cpu.halted(&program));
+        assert_eq!(seen[..4], [(1, 1), (2, 1), (3, 3), (4, 3)]);
+        assert_eq!(seen.last(), Some(&(10, 15)));
+
+        let mut custom = Registry::new();
+        custom.register(InstrDef {
+            name: "addx",
+            arity: 2,
+            cycles: 1,
+            exec: |cpu, args| {
+                cpu.x += args[0] * args[1];
+                Ok(())
+            },
+        });
+        let program = parse(["addx 3 4"].into_iter(), &custom).unwrap();
+        assert_eq!(program[0].to_string(), "addx 3 4");
+        let mut cpu = Cpu::new();
+        cpu.run(&program, &mut |_: &Cpu| (), 100).unwrap();
+        assert_eq!((cpu.x, cpu.cycle), (13, 1));
+    }
+
+    #[test]
+    fn test_overflow() {
+        let registry = Registry::default();
+        let run = |lines: &[&str]| {
+            let program = parse(lines.iter().copied(), &registry).unwrap();
+            let mut cpu = Cpu::new();
+            let result = cpu.run(&program, &mut |_: &Cpu| (), 100);
+            (result, cpu.x)
+        };
+        let error = RunError::Failed {
+            pc: 1,
+            cycle: 6,
+            message: "X overflowed".to_owned(),
+        };
+        assert_eq!(
+            run(&["addx 99999", "mul 100000"]),
+            (Err(error.clone()), 100000)
+        );
+        assert_eq!(
+            error.to_string(),
+            "instruction 1 failed after cycle 6: X overflowed"
+        );
+        assert!(run(&["addx 2147483647"]).0.is_err());
+        assert!(run(&["addy -2147483648", "addy -1"]).0.is_err());
+
+        // X * cycle no longer fits in an i32, but the signal strength does fit in an i64.
+        let mut program = vec!["addx 100000", "mul 1000"];
+        program.extend(["noop"; 214]);
+        let program = parse(program.into_iter(), &registry).unwrap();
+        assert_eq!(part1(&program), Ok(720 * 100001000));
+    }
+
+    #[test]
+    fn test_endless_loop() {
+        let program = parse(["noop", "jmp 0"].into_iter(), &Registry::default()).unwrap();
+        let mut cpu = Cpu::new();
+        assert_eq!(
+            cpu.run(&program, &mut |_: &Cpu| (), 50),
+            Err(RunError::OutOfCycles { pc: 1, cycle: 50 })
+        );
+        assert_eq!(
+            part1(&program),
+            Err(RunError::OutOfCycles {
+                pc: 1,
+                cycle: MAX_CYCLES
+            })
+        );
+        assert!(part2(&program).is_err());
+
+        let debugger = Debugger {
+            breakpoints: vec![Breakpoint::Cycle(2)],
+            max_cycles: 10,
+            ..Debugger::default()
+        };
+        let (stops, trace) = debugger.run(&program);
+        assert_eq!(trace.lines().count(), 10);
+        let last = stops.lines().rfind(|l| l.starts_with("=="));
+        assert_eq!(
+            last,
+            Some(concat!(
+                "== after cycle 10, pc 1 `jmp 0`: ",
+                "still running at pc 1 after 10 cycles, is it stuck in a loop?"
+            ))
+        );
+
+        let program = parse(["addx 2147483647"].into_iter(), &Registry::default()).unwrap();
+        let (stops, _) = Debugger::default().run(&program);
+        let last = stops.lines().rfind(|l| l.starts_with("=="));
+        assert_eq!(
+            last,
+            Some("== after cycle 2, halted: instruction 0 failed after cycle 2: X overflowed")
+        );
+    }
+
+    fn draw(text: &str) -> String {
+        (0..6)
+            .map(|row| {
+                let line = text
+                    .chars()
+                    .map(|c| {
+                        let (_, glyph) = GLYPHS.iter().find(|&&(g, _)| g == c).unwrap();
+                        format!("{}.", &glyph[row * 4..row * 4 + 4])
+                    })
+                    .collect::<String>();
+                line + "\n"
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_ocr() {
+        assert_eq!(ocr(&draw("EHZFZHCZ")), Ok("EHZFZHCZ".to_owned()));
+        let all = GLYPHS.iter().map(|&(c, _)| c).collect::<String>();
+        assert_eq!(ocr(&draw(&all)), Ok(all));
+
+        // Trailing blank columns may be cut off.
+        let trimmed = draw("LU")
+            .lines()
+            .map(|l| format!("{}\n", &l[..9]))
+            .collect::<String>();
+        assert_eq!(ocr(&trimmed), Ok("LU".to_owned()));
+
+        let root = parse(sample(), &Registry::default()).unwrap();
+        let error = ocr(&part2(&root).unwrap()).unwrap_err();
+        assert_eq!(error.position, 0);
+        assert_eq!(error.glyph.lines().next(), Some("##..#"));
+    }
+
+    #[test]
+    fn test_emit_and_listing() {
+        let registry = Registry::default();
+        let program = parse(
+            ["  addx   15", "noop", "", "mul -2 "].into_iter(),
+            &registry,
+        )
+        .unwrap();
+        let source = emit(&program);
+        assert_eq!(source, "addx 15\nnoop\nmul -2\n");
+        assert_eq!(parse(source.lines(), &registry), Ok(program.clone()));
+        assert_eq!(
+            listing(&program),
+            "   0  addx 15     ; cycle 1-2\n   1  noop        ; cycle 3\n   2  mul -2      ; cycle 4-7\n"
+        );
+    }
+
+    #[test]
+    fn test_debugger() {
+        assert_eq!("cycle:20".parse(), Ok(Breakpoint::Cycle(20)));
+        assert_eq!("instr:3".parse(), Ok(Breakpoint::Instr(3)));
+        assert!("line:3".parse::<Breakpoint>().is_err());
+        assert_eq!("x".parse(), Ok(Watch::Changes(Reg::X)));
+        assert_eq!("Y=-4".parse(), Ok(Watch::Equals(Reg::Y, -4)));
+        assert!("z".parse::<Watch>().is_err());
+
+        let program = parse(
+            ["noop", "addx 3", "addx -5"].into_iter(),
+            &Registry::default(),
+        )
+        .unwrap();
+        let (stops, trace) = Debugger::default().run(&program);
+        assert_eq!(stops.matches("== ").count(), 3);
+        assert!(stops.starts_with("== before cycle 1, pc 0 `noop`: step\nX=1 Y=0\nsprite ###....."));
+        assert_eq!(trace.lines().count(), 5);
+        assert_eq!(
+            trace.lines().nth(3),
+            Some("    4    2  addx -5      X=4 Y=0")
+        );
+
+        let debugger = Debugger {
+            breakpoints: vec![Breakpoint::Cycle(3), Breakpoint::Instr(2)],
+            watches: vec![Watch::Changes(Reg::X), Watch::Equals(Reg::X, -1)],
+            ..Debugger::default()
+        };
+        let (stops, _) = debugger.run(&program);
+        let headers = stops
+            .lines()
+            .filter(|l| l.starts_with("=="))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            headers,
+            [
+                "== during cycle 3, pc 1 `addx 3`: breakpoint cycle:3",
+                "== after cycle 3, pc 2 `addx -5`: watch X: X 1 -> 4",
+                "== before cycle 4, pc 2 `addx -5`: breakpoint instr:2",
+                "== after cycle 5, halted: watch X: X 4 -> -1",
+                "== after cycle 5, halted: watch X=-1: X 4 -> -1",
+            ]
+        );
+        assert!(stops
+            .contains("X=1 Y=0\nsprite ###.....................................\ncrt    ###\n"));
+    }
+
+    #[test]
+    fn test_stop_at_end_of_row() {
+        let program = parse(["noop"; 41].into_iter(), &Registry::default()).unwrap();
+        let debugger = Debugger {
+            breakpoints: vec![Breakpoint::Cycle(40), Breakpoint::Instr(40)],
+            ..Debugger::default()
+        };
+        let (stops, _) = debugger.run(&program);
+        let rows = stops
+            .lines()
+            .filter_map(|l| l.strip_prefix("crt    "))
+            .collect::<Vec<_>>();
+        let full_row = format!("###{}", ".".repeat(37));
+        assert_eq!(rows, [full_row.as_str(), full_row.as_str()]);
+    }
 }
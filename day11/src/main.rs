@@ -1,41 +1,320 @@
-use core::panic;
 use std::{
-    collections::{HashSet},
+    collections::HashMap,
+    env,
+    fmt::{self, Display, Formatter},
     io::{self, BufRead},
-    ops::Deref, mem::take,
+    iter::Peekable,
+    mem::take,
+    ops::Deref,
+    str::FromStr,
 };
 
-#[derive(Clone, Copy)]
-enum Op {
-    Add(u64),
-    Mult(u64),
-    Square,
+/// The right hand side of `new = ...`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Expr {
+    Old,
+    Const(u64),
+    Add(Box<Expr>, Box<Expr>),
+    Mul(Box<Expr>, Box<Expr>),
 }
 
-impl Op {
-    fn op(self, level: u64) -> u64 {
+impl Expr {
+    fn eval<W: Worry>(&self, old: &W) -> W {
         match self {
-            Op::Add(c) => level + c,
-            Op::Mult(c) => level * c,
-            Op::Square => level * level,
+            Expr::Old => old.clone(),
+            Expr::Const(c) => W::from_u64(*c),
+            Expr::Add(a, b) => a.eval(old).add(&b.eval(old)),
+            Expr::Mul(a, b) => a.eval(old).mul(&b.eval(old)),
         }
     }
+
+    /// Like `eval`, but `None` if anything along the way overflows.
+    fn checked_eval(&self, old: u64) -> Option<u64> {
+        match self {
+            Expr::Old => Some(old),
+            Expr::Const(c) => Some(*c),
+            Expr::Add(a, b) => a.checked_eval(old)?.checked_add(b.checked_eval(old)?),
+            Expr::Mul(a, b) => a.checked_eval(old)?.checked_mul(b.checked_eval(old)?),
+        }
+    }
+}
+
+/// A worry level.
+trait Worry: Clone {
+    fn from_u64(n: u64) -> Self;
+    fn add(&self, other: &Self) -> Self;
+    fn mul(&self, other: &Self) -> Self;
+    fn div_rem(&self, d: u64) -> (Self, u64);
+}
+
+impl Worry for u64 {
+    fn from_u64(n: u64) -> u64 {
+        n
+    }
+    fn add(&self, other: &u64) -> u64 {
+        self + other
+    }
+    fn mul(&self, other: &u64) -> u64 {
+        self * other
+    }
+    fn div_rem(&self, d: u64) -> (u64, u64) {
+        (self / d, self % d)
+    }
+}
+
+/// Just enough of an arbitrary-precision unsigned integer to hold exact worry levels:
+/// little-endian 32 bit limbs without trailing zeros.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct BigUint(Vec<u32>);
+
+impl BigUint {
+    fn trimmed(mut limbs: Vec<u32>) -> BigUint {
+        while limbs.last() == Some(&0) {
+            limbs.pop();
+        }
+        BigUint(limbs)
+    }
+}
+
+impl Worry for BigUint {
+    fn from_u64(n: u64) -> BigUint {
+        BigUint::trimmed(vec![n as u32, (n >> 32) as u32])
+    }
+
+    fn add(&self, other: &BigUint) -> BigUint {
+        let mut limbs = Vec::with_capacity(self.0.len().max(other.0.len()) + 1);
+        let mut carry = 0;
+        for i in 0..self.0.len().max(other.0.len()) {
+            let sum =
+                carry + *self.0.get(i).unwrap_or(&0) as u64 + *other.0.get(i).unwrap_or(&0) as u64;
+            limbs.push(sum as u32);
+            carry = sum >> 32;
+        }
+        limbs.push(carry as u32);
+        BigUint::trimmed(limbs)
+    }
+
+    fn mul(&self, other: &BigUint) -> BigUint {
+        let mut limbs = vec![0u32; self.0.len() + other.0.len()];
+        for (i, &a) in self.0.iter().enumerate() {
+            let mut carry = 0;
+            for (j, &b) in other.0.iter().enumerate() {
+                let product = limbs[i + j] as u64 + a as u64 * b as u64 + carry;
+                limbs[i + j] = product as u32;
+                carry = product >> 32;
+            }
+            limbs[i + other.0.len()] = carry as u32;
+        }
+        BigUint::trimmed(limbs)
+    }
+
+    fn div_rem(&self, d: u64) -> (BigUint, u64) {
+        let mut quotient = vec![0; self.0.len()];
+        let mut rem = 0u128;
+        for (i, &limb) in self.0.iter().enumerate().rev() {
+            let current = rem << 32 | limb as u128;
+            quotient[i] = (current / d as u128) as u32;
+            rem = current % d as u128;
+        }
+        (BigUint::trimmed(quotient), rem as u64)
+    }
+}
+
+impl Display for Expr {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Expr::Old => write!(f, "old"),
+            Expr::Const(c) => write!(f, "{c}"),
+            Expr::Add(a, b) => write!(f, "{a} + {b}"),
+            Expr::Mul(a, b) => {
+                for (i, e) in [a, b].into_iter().enumerate() {
+                    if i > 0 {
+                        write!(f, " * ")?;
+                    }
+                    match **e {
+                        Expr::Add(..) => write!(f, "({e})")?,
+                        _ => write!(f, "{e}")?,
+                    }
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl FromStr for Expr {
+    type Err = String;
+
+    /// Parses `old`, numbers, `+`, `*` and parentheses, with the usual precedence.
+    fn from_str(s: &str) -> Result<Expr, String> {
+        let mut tokens = vec![];
+        let mut chars = s.char_indices().peekable();
+        while let Some(&(start, c)) = chars.peek() {
+            if c.is_whitespace() {
+                chars.next();
+            } else if c.is_ascii_alphanumeric() {
+                let mut end = start;
+                while let Some(&(i, c)) = chars.peek() {
+                    if !c.is_ascii_alphanumeric() {
+                        break;
+                    }
+                    end = i + c.len_utf8();
+                    chars.next();
+                }
+                tokens.push(&s[start..end]);
+            } else {
+                tokens.push(&s[start..start + c.len_utf8()]);
+                chars.next();
+            }
+        }
+
+        let mut tokens = tokens.into_iter().peekable();
+        let expr = parse_sum(&mut tokens)?;
+        match tokens.next() {
+            None => Ok(expr),
+            Some(token) => Err(format!("unexpected {token:?} in {s:?}")),
+        }
+    }
+}
+
+type Tokens<'a> = Peekable<std::vec::IntoIter<&'a str>>;
+
+fn parse_sum(tokens: &mut Tokens) -> Result<Expr, String> {
+    let mut expr = parse_product(tokens)?;
+    while tokens.next_if_eq(&"+").is_some() {
+        expr = Expr::Add(Box::new(expr), Box::new(parse_product(tokens)?));
+    }
+    Ok(expr)
 }
 
-impl Default for Op {
-    fn default() -> Self {
-        Self::Add(0)
+fn parse_product(tokens: &mut Tokens) -> Result<Expr, String> {
+    let mut expr = parse_atom(tokens)?;
+    while tokens.next_if_eq(&"*").is_some() {
+        expr = Expr::Mul(Box::new(expr), Box::new(parse_atom(tokens)?));
     }
+    Ok(expr)
 }
 
-#[derive(Default)]
+fn parse_atom(tokens: &mut Tokens) -> Result<Expr, String> {
+    match tokens.next() {
+        Some("old") => Ok(Expr::Old),
+        Some("(") => {
+            let expr = parse_sum(tokens)?;
+            match tokens.next() {
+                Some(")") => Ok(expr),
+                _ => Err("missing `)`".to_owned()),
+            }
+        }
+        Some(token) => token
+            .parse()
+            .map(Expr::Const)
+            .map_err(|_| format!("unexpected {token:?}")),
+        None => Err("unexpected end of expression".to_owned()),
+    }
+}
+
+#[derive(Debug, Clone)]
 struct Monkey {
     items: Vec<u64>,
-    op: Op,
+    op: Expr,
     div: u64,
     if_true: usize,
     if_false: usize,
-    inspections: usize,
+}
+
+impl Monkey {
+    fn target(&self, worry: &impl Worry) -> usize {
+        if worry.div_rem(self.div).1 == 0 {
+            self.if_true
+        } else {
+            self.if_false
+        }
+    }
+}
+
+/// How worry levels are kept down after each inspection.
+trait Relief<W> {
+    fn relieve(&self, worry: W) -> W;
+}
+
+/// Relief that the item wasn't damaged divides the worry level.
+struct DivideBy(u64);
+
+impl<W: Worry> Relief<W> for DivideBy {
+    fn relieve(&self, worry: W) -> W {
+        worry.div_rem(self.0).0
+    }
+}
+
+/// No relief at all. Only usable for a few rounds with exact worry levels.
+struct Exact;
+
+impl<W> Relief<W> for Exact {
+    fn relieve(&self, worry: W) -> W {
+        worry
+    }
+}
+
+/// No relief, but the monkeys only ever test worry levels for divisibility, so they are
+/// only needed modulo a multiple of every test.
+#[derive(Debug, PartialEq, Eq)]
+struct Modulo(u64);
+
+impl Relief<u64> for Modulo {
+    fn relieve(&self, worry: u64) -> u64 {
+        worry % self.0
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum OverflowError {
+    /// The least common multiple of the tests doesn't fit.
+    Modulus,
+    /// A monkey's operation on the largest possible worry level doesn't fit.
+    Operation { monkey: usize, old: u64 },
+}
+
+impl Display for OverflowError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            OverflowError::Modulus => write!(f, "the tests' least common multiple overflows u64"),
+            OverflowError::Operation { monkey, old } => {
+                write!(
+                    f,
+                    "Monkey {monkey}'s operation overflows u64 for old = {old}"
+                )
+            }
+        }
+    }
+}
+
+fn gcd(a: u64, b: u64) -> u64 {
+    if b == 0 {
+        a
+    } else {
+        gcd(b, a % b)
+    }
+}
+
+impl Modulo {
+    /// The smallest modulus that keeps every test intact, after checking that no operation
+    /// can overflow on any worry level the monkeys will see. Since operations only add and
+    /// multiply, checking the largest such level is enough.
+    fn for_monkeys(monkeys: &[Monkey]) -> Result<Modulo, OverflowError> {
+        let lcm = monkeys.iter().try_fold(1u64, |lcm, m| {
+            (lcm / gcd(lcm, m.div))
+                .checked_mul(m.div)
+                .ok_or(OverflowError::Modulus)
+        })?;
+        let largest_item = monkeys.iter().flat_map(|m| &m.items).max();
+        let old = largest_item.map_or(0, |&item| item.max(lcm - 1));
+        for (monkey, m) in monkeys.iter().enumerate() {
+            if m.op.checked_eval(old).is_none() {
+                return Err(OverflowError::Operation { monkey, old });
+            }
+        }
+        Ok(Modulo(lcm))
+    }
 }
 
 type Parsed = Vec<Monkey>;
@@ -43,89 +322,254 @@ type Parsed = Vec<Monkey>;
 fn main() {
     let stdin = io::stdin();
     let lines = stdin.lock().lines().map(|x| x.unwrap());
-    let mut map = parse(lines);
-    // println!("part1: {}", part1(&mut map));
-    println!("part2: {}", part2(&mut map));
+    let map = parse(lines).unwrap_or_else(|e| panic!("{e}"));
+
+    let mut args = env::args().skip(1);
+    match args.next().as_deref() {
+        None => {
+            println!("part1: {}", part1(&map));
+            println!("part2: {}", part2(&map));
+        }
+        Some("exact") => {
+            let rounds = args.next().map_or(20, |r| r.parse().unwrap());
+            let exact = monkey_business(simulate::<BigUint>(&map, rounds, &Exact));
+            let relief = Modulo::for_monkeys(&map).unwrap_or_else(|e| panic!("{e}"));
+            let reduced = monkey_business(simulate::<u64>(&map, rounds, &relief));
+            println!("exact: {exact}");
+            println!("modulo {}: {reduced}", relief.0);
+            assert_eq!(exact, reduced, "exact and reduced worry levels disagree");
+        }
+        Some(mode @ ("cycles" | "paths")) => {
+            let rounds = args.next().map_or(10000, |r| r.parse().unwrap());
+            let relief = Modulo::for_monkeys(&map).unwrap_or_else(|e| panic!("{e}"));
+            let trajectories = trajectories(&map, &relief);
+            for (i, t) in trajectories.iter().enumerate() {
+                let ((monkey, worry), _) = t.rounds[0];
+                print!("item {i} (worry {worry} at monkey {monkey}): ");
+                if mode == "cycles" {
+                    println!(
+                        "cycle of {} rounds from round {}",
+                        t.cycle_len(),
+                        t.cycle_start + 1
+                    );
+                } else {
+                    let path = (0..rounds)
+                        .map(|r| t.round(r).iter().map(|m| m.to_string()).collect::<Vec<_>>())
+                        .map(|monkeys| monkeys.join(">"))
+                        .collect::<Vec<_>>();
+                    println!("{}", path.join(" | "));
+                }
+            }
+            let inspections = inspections_by_cycles(&trajectories, map.len(), rounds);
+            println!(
+                "monkey business after {rounds} rounds: {}",
+                monkey_business(inspections)
+            );
+        }
+        Some(other) => panic!("Unknown command: {other}"),
+    }
 }
 
-fn parse(lines: impl Iterator<Item = impl Deref<Target = str>>) -> Parsed {
+fn parse(lines: impl Iterator<Item = impl Deref<Target = str>>) -> Result<Parsed, String> {
     let mut result = Parsed::new();
-    let mut monkey = Monkey::default();
-    for line in lines {
+    let mut monkey: Option<Monkey> = None;
+    for (i, line) in lines.enumerate() {
+        let error = |message: String| format!("line {}: {message}", i + 1);
         let line: &str = line.trim();
-        if let Some(rest) = line.strip_prefix("Starting items: ") {
-            monkey.items = rest.split(", ").map(|x|x.parse::<u64>().unwrap()).collect::<Vec<_>>();
-        } else if let Some(rest) = line.strip_prefix("Operation: new = ") {
-            monkey.op = if rest == "old * old" {
-                Op::Square
-            } else if let Some(c) = rest.strip_prefix("old * ") {
-                Op::Mult(c.parse().unwrap())
-            } else if let Some(c) = rest.strip_prefix("old + ") {
-                Op::Add(c.parse().unwrap())
-            } else {
-                panic!("Unexpected: {rest}");
+        let number = |rest: &str| {
+            rest.parse()
+                .map_err(|_| error(format!("bad number {rest:?}")))
+        };
+        if line.is_empty() {
+            continue;
+        }
+        if let Some(rest) = line.strip_prefix("Monkey ") {
+            if rest.strip_suffix(':') != Some(&result.len().to_string()) {
+                return Err(error(format!("expected Monkey {}:", result.len())));
             }
+            monkey = Some(Monkey {
+                items: vec![],
+                op: Expr::Old,
+                div: 1,
+                if_true: 0,
+                if_false: 0,
+            });
+            continue;
+        }
+        let Some(monkey) = monkey.as_mut() else {
+            return Err(error("expected a Monkey header".to_owned()));
+        };
+        if let Some(rest) = line.strip_prefix("Starting items:") {
+            monkey.items = rest
+                .split(',')
+                .map(str::trim)
+                .filter(|x| !x.is_empty())
+                .map(number)
+                .collect::<Result<Vec<_>, _>>()?;
+        } else if let Some(rest) = line.strip_prefix("Operation: new = ") {
+            monkey.op = rest.parse().map_err(error)?;
         } else if let Some(rest) = line.strip_prefix("Test: divisible by ") {
-            monkey.div = rest.parse().unwrap();
+            monkey.div = number(rest)?;
+            if monkey.div == 0 {
+                return Err(error("cannot test divisibility by 0".to_owned()));
+            }
         } else if let Some(rest) = line.strip_prefix("If true: throw to monkey ") {
-            monkey.if_true = rest.parse().unwrap();
+            monkey.if_true = number(rest)? as usize;
         } else if let Some(rest) = line.strip_prefix("If false: throw to monkey ") {
-            monkey.if_false = rest.parse().unwrap();
+            monkey.if_false = number(rest)? as usize;
+            result.push(monkey.clone());
+        } else {
+            return Err(error(format!("unexpected {line:?}")));
+        }
+    }
 
-            result.push(take(&mut monkey))
+    for (i, monkey) in result.iter().enumerate() {
+        for target in [monkey.if_true, monkey.if_false] {
+            if target >= result.len() {
+                return Err(format!(
+                    "Monkey {i} throws to monkey {target}, which doesn't exist"
+                ));
+            }
         }
     }
-    result
+    Ok(result)
 }
 
-fn part1(monkeys: &mut Parsed) -> usize {
-    let mut monkeys = monkeys;
-    for _ in 0..20 {
-        for idx in 0..monkeys.len() {
-            let monkey = &mut monkeys[idx];
-            let mut items = take(&mut monkey.items);
-            for item in &mut items {
-                *item = monkey.op.op(*item) / 3;
-            }
-            monkey.inspections += items.len();
-            let (div, if_true, if_false) = (monkey.div, monkey.if_true, monkey.if_false);
-            drop(monkey);
-            for item in items {
-                let test = (item % div) == 0;
-                let next = if test { if_true } else { if_false };
-                monkeys[next].items.push(item);
+/// Plays `rounds` rounds and returns how many items each monkey inspected.
+fn simulate<W: Worry>(monkeys: &[Monkey], rounds: usize, relief: &impl Relief<W>) -> Vec<usize> {
+    let mut items = monkeys
+        .iter()
+        .map(|m| m.items.iter().map(|&i| W::from_u64(i)).collect::<Vec<_>>())
+        .collect::<Vec<_>>();
+    let mut inspections = vec![0; monkeys.len()];
+    for _ in 0..rounds {
+        for (idx, monkey) in monkeys.iter().enumerate() {
+            for item in take(&mut items[idx]) {
+                let item = relief.relieve(monkey.op.eval(&item));
+                inspections[idx] += 1;
+                items[monkey.target(&item)].push(item);
             }
         }
     }
-    let mut inspections = monkeys.iter().map(|x| x.inspections).collect::<Vec<_>>();
+    inspections
+}
+
+/// The product of the two largest inspection counts, which may be large when skipping
+/// ahead many rounds.
+fn monkey_business(mut inspections: Vec<usize>) -> u128 {
     inspections.sort();
-    inspections[inspections.len() - 2..].iter().product()
+    inspections
+        .iter()
+        .rev()
+        .take(2)
+        .map(|&n| n as u128)
+        .product()
 }
 
-fn part2(monkeys: &mut Parsed) -> usize {
-    let mut monkeys = monkeys;
-    let prod: u64 = monkeys.iter().map(|x| x.div).product();
-    eprintln!("prod: {prod}");
-    for _ in 0..10000 {
-        for idx in 0..monkeys.len() {
-            let monkey = &mut monkeys[idx];
-            let mut items = take(&mut monkey.items);
-            for item in &mut items {
-                *item = monkey.op.op(*item) % prod;
+/// Which monkey holds an item and how worried we are about it, at the start of a round.
+type ItemState = (usize, u64);
+
+/// One item's path through the monkeys, round after round, until it repeats.
+///
+/// Items never affect each other, and with worry levels reduced by a [`Modulo`] each one
+/// only has finitely many states, so its path must eventually go round in a cycle.
+struct Trajectory {
+    /// The state at the start of every round before the repeat, and the monkeys that
+    /// inspect the item during that round.
+    rounds: Vec<(ItemState, Vec<usize>)>,
+    /// The round whose starting state comes back after the last one.
+    cycle_start: usize,
+}
+
+impl Trajectory {
+    fn new(monkeys: &[Monkey], relief: &Modulo, start: ItemState) -> Trajectory {
+        let mut seen = HashMap::new();
+        let mut rounds = vec![];
+        let mut state = start;
+        let cycle_start = loop {
+            if let Some(&round) = seen.get(&state) {
+                break round;
             }
-            monkey.inspections += items.len();
-            let (div, if_true, if_false) = (monkey.div, monkey.if_true, monkey.if_false);
-            drop(monkey);
-            for item in items {
-                let test = (item % div) == 0;
-                let next = if test { if_true } else { if_false };
-                monkeys[next].items.push(item);
+            seen.insert(state, rounds.len());
+            let (mut monkey, mut worry) = state;
+            let mut inspected = vec![];
+            // Monkeys take their turns in order, so throws to a later monkey are caught
+            // and inspected again in the same round.
+            loop {
+                inspected.push(monkey);
+                worry = relief.relieve(monkeys[monkey].op.eval(&worry));
+                let next = monkeys[monkey].target(&worry);
+                if next <= monkey {
+                    rounds.push((state, inspected));
+                    state = (next, worry);
+                    break;
+                }
+                monkey = next;
             }
+        };
+        Trajectory {
+            rounds,
+            cycle_start,
         }
     }
-    let mut inspections = monkeys.iter().map(|x| x.inspections).collect::<Vec<_>>();
-    inspections.sort();
-    inspections[inspections.len() - 2..].iter().product()
+
+    fn cycle_len(&self) -> usize {
+        self.rounds.len() - self.cycle_start
+    }
+
+    /// The monkeys that inspect the item during round `r`, counting from 0.
+    fn round(&self, r: usize) -> &[usize] {
+        let r = match r.checked_sub(self.cycle_start) {
+            Some(into_cycle) => self.cycle_start + into_cycle % self.cycle_len(),
+            None => r,
+        };
+        &self.rounds[r].1
+    }
+}
+
+fn trajectories(monkeys: &[Monkey], relief: &Modulo) -> Vec<Trajectory> {
+    monkeys
+        .iter()
+        .enumerate()
+        .flat_map(|(i, m)| m.items.iter().map(move |&item| (i, item)))
+        .map(|(i, item)| Trajectory::new(monkeys, relief, (i, relief.relieve(item))))
+        .collect()
+}
+
+/// Counts inspections over `rounds` rounds like [`simulate`], but skips over whole cycles
+/// of each item's trajectory instead of playing them.
+fn inspections_by_cycles(trajectories: &[Trajectory], monkeys: usize, rounds: usize) -> Vec<usize> {
+    let mut inspections = vec![0; monkeys];
+    for t in trajectories {
+        let prefix = rounds.min(t.cycle_start);
+        for r in 0..prefix {
+            for &m in t.round(r) {
+                inspections[m] += 1;
+            }
+        }
+        let (cycles, rest) = (
+            (rounds - prefix) / t.cycle_len(),
+            (rounds - prefix) % t.cycle_len(),
+        );
+        for i in 0..t.cycle_len() {
+            let times = cycles + usize::from(i < rest);
+            for &m in t.round(t.cycle_start + i) {
+                inspections[m] += times;
+            }
+        }
+    }
+    inspections
+}
+
+fn part1(monkeys: &Parsed) -> u128 {
+    monkey_business(simulate::<u64>(monkeys, 20, &DivideBy(3)))
+}
+
+fn part2(monkeys: &Parsed) -> u128 {
+    let relief = Modulo::for_monkeys(monkeys).unwrap_or_else(|e| panic!("{e}"));
+    eprintln!("modulo: {}", relief.0);
+    let trajectories = trajectories(monkeys, &relief);
+    monkey_business(inspections_by_cycles(&trajectories, monkeys.len(), 10000))
 }
 
 #[cfg(test)]
@@ -167,13 +611,161 @@ Monkey 3:
 
     #[test]
     fn test_part1() {
-        let mut root = parse(sample());
-        assert_eq!(part1(&mut root), 10605);
+        let root = parse(sample()).unwrap();
+        assert_eq!(part1(&root), 10605);
     }
 
     #[test]
     fn test_part2() {
-        let mut root = parse(sample());
-        assert_eq!(part2(&mut root), 2713310158);
+        let root = parse(sample()).unwrap();
+        assert_eq!(part2(&root), 2713310158);
+    }
+
+    #[test]
+    fn test_expr() {
+        for (source, old, value) in [
+            ("old * old", 7, 49),
+            ("old + old", 7, 14),
+            ("3 * old", 7, 21),
+            ("old + 2 * old", 7, 21),
+            ("(old + 2) * old", 7, 63),
+            ("4", 7, 4),
+        ] {
+            let expr = source.parse::<Expr>().unwrap();
+            assert_eq!(expr.eval(&old), value, "{source}");
+            assert_eq!(expr.to_string(), source);
+        }
+        assert!("old - 1".parse::<Expr>().is_err());
+        assert!("(old + 1".parse::<Expr>().is_err());
+        assert!("old *".parse::<Expr>().is_err());
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        let bad_target = SAMPLE_INPUT.replace("throw to monkey 3", "throw to monkey 4");
+        assert_eq!(
+            parse(bad_target.lines()).unwrap_err(),
+            "Monkey 0 throws to monkey 4, which doesn't exist"
+        );
+        let bad_op = SAMPLE_INPUT.replace("old + 6", "old ^ 6");
+        assert_eq!(
+            parse(bad_op.lines()).unwrap_err(),
+            "line 10: unexpected \"^\" in \"old ^ 6\""
+        );
+        let bad_header = SAMPLE_INPUT.replace("Monkey 2:", "Monkey 5:");
+        assert_eq!(
+            parse(bad_header.lines()).unwrap_err(),
+            "line 15: expected Monkey 2:"
+        );
+    }
+
+    #[test]
+    fn test_big_uint() {
+        let big = |n: u128| {
+            let limbs = (0..4).map(|i| (n >> (32 * i)) as u32).collect();
+            BigUint::trimmed(limbs)
+        };
+        let values = [
+            0,
+            1,
+            7,
+            u32::MAX as u64,
+            1 << 32,
+            0x1234_5678_9abc_def0,
+            u64::MAX - 1,
+            u64::MAX,
+        ];
+        let divisors = [
+            1,
+            3,
+            u32::MAX as u64,
+            1 << 32,
+            0xfedc_ba98_7654_3211,
+            u64::MAX,
+        ];
+        for a in values {
+            for b in values {
+                let (a, b) = (a as u128, b as u128);
+                assert_eq!(
+                    BigUint::from_u64(a as u64).add(&BigUint::from_u64(b as u64)),
+                    big(a + b)
+                );
+                assert_eq!(
+                    BigUint::from_u64(a as u64).mul(&BigUint::from_u64(b as u64)),
+                    big(a * b)
+                );
+                for d in divisors {
+                    let (q, r) = big(a * b).div_rem(d);
+                    assert_eq!((q, r), (big(a * b / d as u128), (a * b % d as u128) as u64));
+                }
+            }
+        }
+        assert_eq!(BigUint::from_u64(0), BigUint(vec![]));
+    }
+
+    #[test]
+    fn test_exact_agrees() {
+        let monkeys = parse(sample()).unwrap();
+        let relief = Modulo::for_monkeys(&monkeys).unwrap();
+        assert_eq!(relief.0, 23 * 19 * 13 * 17);
+        for rounds in [1, 5, 10] {
+            assert_eq!(
+                simulate::<BigUint>(&monkeys, rounds, &Exact),
+                simulate::<u64>(&monkeys, rounds, &relief)
+            );
+        }
+        assert_eq!(
+            monkey_business(simulate::<BigUint>(&monkeys, 20, &DivideBy(3))),
+            part1(&monkeys)
+        );
+    }
+
+    #[test]
+    fn test_overflow_detection() {
+        let shared = SAMPLE_INPUT
+            .replace("by 23", "by 26")
+            .replace("by 19", "by 39");
+        let monkeys = parse(shared.lines()).unwrap();
+        assert_eq!(Modulo::for_monkeys(&monkeys).unwrap().0, 2 * 3 * 13 * 17);
+
+        let huge = SAMPLE_INPUT
+            .replace("by 23", "by 4294967291")
+            .replace("by 19", "by 4294967279");
+        let monkeys = parse(huge.lines()).unwrap();
+        assert_eq!(Modulo::for_monkeys(&monkeys), Err(OverflowError::Modulus));
+
+        let squares = SAMPLE_INPUT.replace("by 23", "by 4294967291");
+        let monkeys = parse(squares.lines()).unwrap();
+        let error = Modulo::for_monkeys(&monkeys).unwrap_err();
+        assert_eq!(
+            error,
+            OverflowError::Operation {
+                monkey: 2,
+                old: 4294967291 * 19 * 13 * 17 - 1
+            }
+        );
+    }
+
+    #[test]
+    fn test_trajectories() {
+        let monkeys = parse(sample()).unwrap();
+        let relief = Modulo::for_monkeys(&monkeys).unwrap();
+        let trajectories = trajectories(&monkeys, &relief);
+        assert_eq!(trajectories.len(), 10);
+        assert_eq!(trajectories[0].rounds[0], ((0, 79), vec![0, 3]));
+        assert_eq!(trajectories[0].rounds[1].0, (1, 1504));
+
+        for t in &trajectories {
+            let len = t.rounds.len();
+            assert_eq!(t.round(len), t.round(t.cycle_start));
+            assert!(t.cycle_len() > 0);
+        }
+        for rounds in [0, 1, 20, 1000, 10000] {
+            assert_eq!(
+                inspections_by_cycles(&trajectories, monkeys.len(), rounds),
+                simulate::<u64>(&monkeys, rounds, &relief),
+                "{rounds}"
+            );
+        }
     }
 }
@@ -1,5 +1,7 @@
 use std::{
-    collections::VecDeque,
+    cmp::Reverse,
+    collections::{BinaryHeap, VecDeque},
+    env,
     io::{self, BufRead},
     ops::{Deref, Index, IndexMut},
 };
@@ -24,7 +26,7 @@ impl<T> Grid<T> {
         }
     }
 
-    fn neighbors(&self, (x, y): (usize, usize)) -> Vec<(usize, usize)> {
+    fn neighbors(&self, (x, y): Pos) -> Vec<Pos> {
         let mut v = Vec::new();
         if x > 0 {
             v.push((x - 1, y));
@@ -43,7 +45,7 @@ impl<T> Grid<T> {
 }
 
 impl<T: PartialEq> Grid<T> {
-    fn position(&self, needle: T) -> Option<(usize, usize)> {
+    fn position(&self, needle: T) -> Option<Pos> {
         let p = self.vec.iter().position(|x| *x == needle);
         p.map(|p| (p % self.dim.0, p / self.dim.0))
     }
@@ -69,6 +71,7 @@ impl<T> IndexMut<(usize, usize)> for Grid<T> {
     }
 }
 
+type Pos = (usize, usize);
 type Parsed = Grid<char>;
 
 fn height(c: char) -> usize {
@@ -80,18 +83,152 @@ fn height(c: char) -> usize {
         - 'a' as usize
 }
 
+/// A route over the heightmap, one square at a time.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Path {
+    squares: Vec<Pos>,
+    /// The sum of the costs of all steps.
+    cost: usize,
+}
+
+impl Path {
+    fn steps(&self) -> usize {
+        self.squares.len() - 1
+    }
+}
+
+/// What it takes to step from one height onto a neighboring one.
+trait Terrain {
+    /// The cost of the step, or `None` if it can't be taken.
+    fn cost(&self, from: usize, to: usize) -> Option<usize>;
+
+    /// Whether every step that can be taken costs 1, so that breadth-first search will do.
+    fn uniform(&self) -> bool;
+
+    /// A lower bound for the cost of getting from one height to another over `distance`
+    /// steps or more, used as the heuristic for A*.
+    fn min_cost(&self, from: usize, to: usize, distance: usize) -> usize;
+}
+
+/// Steps may climb and descend by limited amounts, and cost 1 plus a price per unit of
+/// height climbed or descended.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Rules {
+    max_climb: usize,
+    max_descent: usize,
+    climb_cost: usize,
+    descent_cost: usize,
+}
+
+impl Rules {
+    /// Climb at most one step, drop any amount.
+    const PUZZLE: Rules = Rules {
+        max_climb: 1,
+        max_descent: usize::MAX,
+        climb_cost: 0,
+        descent_cost: 0,
+    };
+}
+
+impl Terrain for Rules {
+    fn cost(&self, from: usize, to: usize) -> Option<usize> {
+        let (up, down) = (to.saturating_sub(from), from.saturating_sub(to));
+        (up <= self.max_climb && down <= self.max_descent)
+            .then(|| 1 + up * self.climb_cost + down * self.descent_cost)
+    }
+
+    fn uniform(&self) -> bool {
+        self.climb_cost == 0 && self.descent_cost == 0
+    }
+
+    fn min_cost(&self, from: usize, to: usize, distance: usize) -> usize {
+        let (up, down) = (to.saturating_sub(from), from.saturating_sub(to));
+        let steps = distance
+            .max(up.div_ceil(self.max_climb.max(1)))
+            .max(down.div_ceil(self.max_descent.max(1)));
+        steps + up * self.climb_cost + down * self.descent_cost
+    }
+}
+
+/// The same terrain walked backwards, for searching from the destination.
+struct Reversed<'a, T>(&'a T);
+
+impl<T: Terrain> Terrain for Reversed<'_, T> {
+    fn cost(&self, from: usize, to: usize) -> Option<usize> {
+        self.0.cost(to, from)
+    }
+
+    fn uniform(&self) -> bool {
+        self.0.uniform()
+    }
+
+    fn min_cost(&self, from: usize, to: usize, distance: usize) -> usize {
+        self.0.min_cost(to, from, distance)
+    }
+}
+
 fn main() {
     let stdin = io::stdin();
     let lines = stdin.lock().lines().map(|x| x.unwrap());
     let map = parse(lines);
-    println!("part1: {}", part1(&map));
-    println!("part2: {}", part2(&map));
+
+    let mut rules = Rules::PUZZLE;
+    let mut args = env::args().skip(1).peekable();
+    let command = args.next_if(|arg| !arg.starts_with("--"));
+    let command_arg = args.next_if(|arg| !arg.starts_with("--"));
+    while let Some(flag) = args.next() {
+        let value = args.next().and_then(|v| v.parse().ok());
+        let value = value.unwrap_or_else(|| panic!("{flag} needs a number"));
+        match flag.as_str() {
+            "--max-climb" => rules.max_climb = value,
+            "--max-descent" => rules.max_descent = value,
+            "--climb-cost" => rules.climb_cost = value,
+            "--descent-cost" => rules.descent_cost = value,
+            _ => panic!("Unknown option: {flag}"),
+        }
+    }
+
+    if let Some(command) = command {
+        let field = distance_field(&map, &rules);
+        match (command.as_str(), command_arg.as_deref()) {
+            ("field", Some("grid") | None) => print!("{}", field_grid(&field)),
+            ("field", Some("csv")) => print!("{}", field_csv(&field)),
+            ("field", Some("heat")) => print!("{}", heat_map(&map, &field)),
+            ("starts", k) => {
+                let k = k.map_or(10, |k| k.parse().unwrap());
+                for ((x, y), distance) in closest_starts(&map, &field, k) {
+                    println!("{x},{y}: {distance}");
+                }
+            }
+            ("unreachable", None) => {
+                for (x, y) in unreachable(&field) {
+                    println!("{x},{y}");
+                }
+            }
+            _ => panic!("Unknown command: {command}"),
+        }
+        return;
+    }
+
+    let routes = [
+        ("part1", part1(&map, &rules)),
+        ("part2", part2(&map, &rules)),
+    ];
+    for (part, path) in routes {
+        match path {
+            Some(path) => {
+                eprint!("{}", render(&map, &path));
+                println!("{part}: {} (cost {})", path.steps(), path.cost);
+            }
+            None => println!("{part}: unreachable"),
+        }
+    }
 }
 
 fn parse(lines: impl Iterator<Item = impl Deref<Target = str>>) -> Parsed {
     let mut result = Parsed::new((0, 0));
     for line in lines {
-        let line: &str = &*line.trim();
+        let line: &str = line.trim();
         result.dim.0 = line.len();
         for c in line.chars() {
             result.vec.push(c)
@@ -101,47 +238,250 @@ fn parse(lines: impl Iterator<Item = impl Deref<Target = str>>) -> Parsed {
     result
 }
 
-fn part1(parsed: &Parsed) -> usize {
-    let mut q = VecDeque::new();
-    let mut d = parsed.clone_with(usize::max_value());
-    let start = parsed.position('S').unwrap();
-    q.push_back(start);
-    d[start] = 0;
-    while let Some(p) = q.pop_front() {
-        let dn = d[p] + 1;
-        for n in parsed.neighbors(p) {
-            if height(parsed[n]) <= height(parsed[p]) + 1 {
-                if dn < d[n] {
-                    d[n] = dn;
+/// Follows `came_from` back from `end` to the square that came from itself.
+fn trace(came_from: &Grid<Option<Pos>>, end: Pos) -> Vec<Pos> {
+    let mut squares = vec![end];
+    while let Some(&last) = squares.last() {
+        match came_from[last] {
+            Some(prev) if prev != last => squares.push(prev),
+            _ => break,
+        }
+    }
+    squares.reverse();
+    squares
+}
+
+/// The cheapest path from `start` to any square satisfying `is_goal`.
+///
+/// Breadth-first search when every step costs the same, A* otherwise. `heuristic` must
+/// never overestimate the remaining cost, nor drop by more than the cost of a step.
+fn search(
+    parsed: &Parsed,
+    start: Pos,
+    terrain: &impl Terrain,
+    is_goal: impl Fn(Pos) -> bool,
+    heuristic: impl Fn(Pos) -> usize,
+) -> Option<Path> {
+    let step_cost = |p: Pos, n: Pos| terrain.cost(height(parsed[p]), height(parsed[n]));
+    let mut came_from: Grid<Option<Pos>> = parsed.clone_with(None);
+    came_from[start] = Some(start);
+
+    if terrain.uniform() {
+        let mut q = VecDeque::new();
+        q.push_back(start);
+        while let Some(p) = q.pop_front() {
+            if is_goal(p) {
+                let squares = trace(&came_from, p);
+                let cost = squares.len() - 1;
+                return Some(Path { squares, cost });
+            }
+            for n in parsed.neighbors(p) {
+                if came_from[n].is_none() && step_cost(p, n).is_some() {
+                    came_from[n] = Some(p);
                     q.push_back(n);
                 }
             }
         }
+        return None;
     }
-    d[parsed.position('E').unwrap()]
+
+    let mut best = parsed.clone_with(usize::MAX);
+    let mut done = parsed.clone_with(false);
+    let mut q = BinaryHeap::new();
+    best[start] = 0;
+    q.push(Reverse((heuristic(start), start)));
+    while let Some(Reverse((_, p))) = q.pop() {
+        if done[p] {
+            continue;
+        }
+        done[p] = true;
+        if is_goal(p) {
+            let squares = trace(&came_from, p);
+            return Some(Path {
+                squares,
+                cost: best[p],
+            });
+        }
+        for n in parsed.neighbors(p) {
+            if let Some(cost) = step_cost(p, n) {
+                let dn = best[p] + cost;
+                if dn < best[n] {
+                    best[n] = dn;
+                    came_from[n] = Some(p);
+                    q.push(Reverse((dn + heuristic(n), n)));
+                }
+            }
+        }
+    }
+    None
+}
+
+fn manhattan((x, y): Pos, (u, v): Pos) -> usize {
+    x.abs_diff(u) + y.abs_diff(v)
+}
+
+/// The cheapest path from `S` to `E`.
+fn part1(parsed: &Parsed, terrain: &impl Terrain) -> Option<Path> {
+    let start = parsed.position('S')?;
+    let end = parsed.position('E')?;
+    let end_height = height(parsed[end]);
+    search(
+        parsed,
+        start,
+        terrain,
+        |p| p == end,
+        |p| terrain.min_cost(height(parsed[p]), end_height, manhattan(p, end)),
+    )
+}
+
+/// The cheapest path from any square at height `a` to `E`, found by walking back from `E`.
+fn part2(parsed: &Parsed, terrain: &impl Terrain) -> Option<Path> {
+    let end = parsed.position('E')?;
+    let reversed = Reversed(terrain);
+    let mut path = search(
+        parsed,
+        end,
+        &reversed,
+        |p| height(parsed[p]) == height('a'),
+        |p| reversed.min_cost(height(parsed[p]), height('a'), 0),
+    )?;
+    path.squares.reverse();
+    Some(path)
 }
 
-fn part2(parsed: &Parsed) -> usize {
-    let mut q = VecDeque::new();
-    let mut d = parsed.clone_with(usize::max_value());
-    let end = parsed.position('E').unwrap();
-    q.push_back(end);
-    d[end] = 0;
-    while let Some(p) = q.pop_front() {
-        if height(parsed[p]) == height('a') {
-            return d[p];
+/// Draws the path like the puzzle does: an arrow on each square pointing to the next one,
+/// `E` where it ends and `.` everywhere else.
+fn render(parsed: &Parsed, path: &Path) -> String {
+    let mut drawing = parsed.clone_with('.');
+    for step in path.squares.windows(2) {
+        let ((x, y), (nx, ny)) = (step[0], step[1]);
+        drawing[step[0]] = match (nx as isize - x as isize, ny as isize - y as isize) {
+            (1, 0) => '>',
+            (-1, 0) => '<',
+            (0, 1) => 'v',
+            (0, -1) => '^',
+            _ => panic!("{:?} and {:?} aren't adjacent", step[0], step[1]),
+        };
+    }
+    if let Some(&last) = path.squares.last() {
+        drawing[last] = 'E';
+    }
+    let mut result = String::new();
+    for row in drawing.vec.chunks(drawing.dim.0) {
+        result.extend(row);
+        result.push('\n');
+    }
+    result
+}
+
+/// The cost of the cheapest path from every square to `E`, or `None` where there is none.
+///
+/// Found with Dijkstra's algorithm walking back from `E`, so it works for any terrain.
+fn distance_field(parsed: &Parsed, terrain: &impl Terrain) -> Grid<Option<usize>> {
+    let mut field = parsed.clone_with(None);
+    let Some(end) = parsed.position('E') else {
+        return field;
+    };
+    let reversed = Reversed(terrain);
+    let mut q = BinaryHeap::new();
+    q.push(Reverse((0, end)));
+    while let Some(Reverse((d, p))) = q.pop() {
+        if field[p].is_some() {
+            continue;
         }
-        let dn = d[p] + 1;
+        field[p] = Some(d);
         for n in parsed.neighbors(p) {
-            if height(parsed[n]) + 1 >= height(parsed[p]) {
-                if dn < d[n] {
-                    d[n] = dn;
-                    q.push_back(n);
+            if field[n].is_none() {
+                if let Some(cost) = reversed.cost(height(parsed[p]), height(parsed[n])) {
+                    q.push(Reverse((d + cost, n)));
                 }
             }
         }
     }
-    d[parsed.position('E').unwrap()]
+    field
+}
+
+/// The field as right-aligned numbers, with `-` for squares that can't reach `E`.
+fn field_grid(field: &Grid<Option<usize>>) -> String {
+    let cell = |d: &Option<usize>| d.map_or("-".to_owned(), |d| d.to_string());
+    let width = field.vec.iter().map(|d| cell(d).len()).max().unwrap_or(0);
+    let mut result = String::new();
+    for row in field.vec.chunks(field.dim.0) {
+        let row = row
+            .iter()
+            .map(|d| format!("{:>width$}", cell(d)))
+            .collect::<Vec<_>>();
+        result += &row.join(" ");
+        result.push('\n');
+    }
+    result
+}
+
+/// The field as CSV, one row per line, with empty cells for squares that can't reach `E`.
+fn field_csv(field: &Grid<Option<usize>>) -> String {
+    let mut result = String::new();
+    for row in field.vec.chunks(field.dim.0) {
+        let row = row
+            .iter()
+            .map(|d| d.map_or(String::new(), |d| d.to_string()))
+            .collect::<Vec<_>>();
+        result += &row.join(",");
+        result.push('\n');
+    }
+    result
+}
+
+/// The heightmap colored from green next to `E` to red furthest away, using ANSI
+/// 256-color backgrounds, and dark gray for squares that can't reach `E`.
+fn heat_map(parsed: &Parsed, field: &Grid<Option<usize>>) -> String {
+    let max = field
+        .vec
+        .iter()
+        .flatten()
+        .max()
+        .copied()
+        .unwrap_or(0)
+        .max(1);
+    let mut result = String::new();
+    for (row, distances) in parsed
+        .vec
+        .chunks(parsed.dim.0)
+        .zip(field.vec.chunks(field.dim.0))
+    {
+        for (&c, d) in row.iter().zip(distances) {
+            let color = match d {
+                // Red and green from the 6x6x6 color cube.
+                Some(d) => {
+                    let red = d * 5 / max;
+                    16 + 36 * red + 6 * (5 - red)
+                }
+                None => 236,
+            };
+            result += &format!("\x1b[30;48;5;{color}m{c}");
+        }
+        result += "\x1b[0m\n";
+    }
+    result
+}
+
+/// The `k` squares at height `a` closest to `E`, closest first.
+fn closest_starts(parsed: &Parsed, field: &Grid<Option<usize>>, k: usize) -> Vec<(Pos, usize)> {
+    let mut starts = (0..parsed.dim.1)
+        .flat_map(|y| (0..parsed.dim.0).map(move |x| (x, y)))
+        .filter(|&p| height(parsed[p]) == height('a'))
+        .filter_map(|p| Some((p, field[p]?)))
+        .collect::<Vec<_>>();
+    starts.sort_by_key(|&((x, y), d)| (d, y, x));
+    starts.truncate(k);
+    starts
+}
+
+/// The squares from which `E` can't be reached at all.
+fn unreachable(field: &Grid<Option<usize>>) -> Vec<Pos> {
+    (0..field.dim.1)
+        .flat_map(|y| (0..field.dim.0).map(move |x| (x, y)))
+        .filter(|&p| field[p].is_none())
+        .collect()
 }
 
 #[cfg(test)]
@@ -162,12 +502,179 @@ mod test {
     #[test]
     fn test_part1() {
         let root = parse(sample());
-        assert_eq!(part1(&root), 31);
+        assert_eq!(part1(&root, &Rules::PUZZLE).unwrap().steps(), 31);
     }
 
     #[test]
     fn test_part2() {
         let root = parse(sample());
-        assert_eq!(part2(&root), 36);
+        assert_eq!(part2(&root, &Rules::PUZZLE).unwrap().steps(), 29);
+    }
+
+    #[test]
+    fn test_paths() {
+        let root = parse(sample());
+        let path = part1(&root, &Rules::PUZZLE).unwrap();
+        assert_eq!(path.squares.first(), Some(&(0, 0)));
+        assert_eq!(path.squares.last(), Some(&(5, 2)));
+        for step in path.squares.windows(2) {
+            assert!(root.neighbors(step[0]).contains(&step[1]));
+            assert!(height(root[step[1]]) <= height(root[step[0]]) + 1);
+        }
+        let drawing = render(&root, &path);
+        assert_eq!(drawing.matches(['>', 'v', '<', '^']).count(), 31);
+        assert_eq!(drawing.lines().nth(2).map(|l| &l[4..6]), Some(">E"));
+
+        let path = part2(&root, &Rules::PUZZLE).unwrap();
+        assert_eq!(height(root[path.squares[0]]), 0);
+        assert_eq!(path.squares.last(), Some(&(5, 2)));
+
+        let walled = parse(["Sbcz", "zzzE"].into_iter());
+        assert_eq!(part1(&walled, &Rules::PUZZLE), None);
+        assert_eq!(part2(&walled, &Rules::PUZZLE).map(|p| p.steps()), None);
+        let trivial = parse(["SaE"].into_iter());
+        assert_eq!(part2(&trivial, &Rules::PUZZLE), None);
+    }
+
+    /// Forces A* even where breadth-first search would do.
+    struct Weighted(Rules);
+
+    impl Terrain for Weighted {
+        fn cost(&self, from: usize, to: usize) -> Option<usize> {
+            self.0.cost(from, to)
+        }
+
+        fn uniform(&self) -> bool {
+            false
+        }
+
+        fn min_cost(&self, from: usize, to: usize, distance: usize) -> usize {
+            self.0.min_cost(from, to, distance)
+        }
+    }
+
+    /// Dijkstra without a heuristic.
+    fn dijkstra(parsed: &Parsed, terrain: &impl Terrain) -> Option<usize> {
+        let start = parsed.position('S')?;
+        let end = parsed.position('E')?;
+        search(parsed, start, terrain, |p| p == end, |_| 0).map(|p| p.cost)
+    }
+
+    #[test]
+    fn test_rules() {
+        let root = parse(sample());
+        assert_eq!(part1(&root, &Weighted(Rules::PUZZLE)).unwrap().cost, 31);
+        assert_eq!(part2(&root, &Weighted(Rules::PUZZLE)).unwrap().cost, 29);
+
+        let free_climbing = Rules {
+            max_climb: 25,
+            ..Rules::PUZZLE
+        };
+        assert_eq!(part1(&root, &free_climbing).unwrap().steps(), 7);
+        let flat = Rules {
+            max_climb: 0,
+            ..Rules::PUZZLE
+        };
+        assert_eq!(part1(&root, &flat), None);
+
+        let effort = Rules {
+            max_climb: 25,
+            climb_cost: 2,
+            ..Rules::PUZZLE
+        };
+        let path = part1(&root, &effort).unwrap();
+        let cost = path
+            .squares
+            .windows(2)
+            .map(|s| effort.cost(height(root[s[0]]), height(root[s[1]])).unwrap())
+            .sum::<usize>();
+        assert_eq!(path.cost, cost);
+        assert_eq!(Some(path.cost), dijkstra(&root, &effort));
+    }
+
+    #[test]
+    fn test_a_star_matches_dijkstra() {
+        let maps = [
+            SAMPLE_INPUT,
+            "SzE",
+            "Sabcdefghijklmnopqrstuvwxyz\nzyxwvutsrqponmlkjihgfedcbaE",
+            "SmzE\nzazb\nqbca",
+            "azzzz\nSazEz\nazzzz",
+            "kSdkq\nmxcaf\nrEbzp\ngzhuw",
+        ];
+        let rules = [
+            Rules::PUZZLE,
+            Rules {
+                max_climb: 25,
+                max_descent: 25,
+                climb_cost: 1,
+                descent_cost: 0,
+            },
+            Rules {
+                max_climb: 3,
+                max_descent: 2,
+                climb_cost: 2,
+                descent_cost: 1,
+            },
+            Rules {
+                max_climb: 10,
+                max_descent: 0,
+                climb_cost: 0,
+                descent_cost: 3,
+            },
+            Rules {
+                max_climb: 0,
+                max_descent: 25,
+                climb_cost: 3,
+                descent_cost: 0,
+            },
+        ];
+        for map in maps {
+            let map = parse(map.lines());
+            for rules in rules {
+                let a_star = part1(&map, &Weighted(rules)).map(|p| p.cost);
+                assert_eq!(a_star, dijkstra(&map, &rules), "{rules:?} {:?}", map.vec);
+                if rules.uniform() {
+                    assert_eq!(part1(&map, &rules).map(|p| p.cost), a_star);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_distance_field() {
+        let root = parse(sample());
+        let field = distance_field(&root, &Rules::PUZZLE);
+        assert_eq!(field[root.position('S').unwrap()], Some(31));
+        assert_eq!(field[root.position('E').unwrap()], Some(0));
+        assert_eq!(unreachable(&field), []);
+        let starts = closest_starts(&root, &field, 3);
+        assert_eq!(starts[0], ((0, 4), 29));
+        assert_eq!(starts.len(), 3);
+        assert!(starts.windows(2).all(|w| w[0].1 <= w[1].1));
+
+        let effort = Rules {
+            max_climb: 25,
+            climb_cost: 2,
+            ..Rules::PUZZLE
+        };
+        let field = distance_field(&root, &effort);
+        let from_start = part1(&root, &effort).unwrap().cost;
+        assert_eq!(field[root.position('S').unwrap()], Some(from_start));
+        let best = part2(&root, &effort).unwrap().cost;
+        assert_eq!(closest_starts(&root, &field, 1)[0].1, best);
+    }
+
+    #[test]
+    fn test_field_exports() {
+        let map = parse(["SzE", "abc"].into_iter());
+        let field = distance_field(&map, &Rules::PUZZLE);
+        assert_eq!(unreachable(&field), [(0, 0), (0, 1), (1, 1), (2, 1)]);
+        assert_eq!(field_grid(&field), "- 1 0\n- - -\n");
+        assert_eq!(field_csv(&field), ",1,0\n,,\n");
+        assert_eq!(closest_starts(&map, &field, 5), []);
+        let heat = heat_map(&map, &field);
+        assert_eq!(heat.lines().count(), 2);
+        assert!(heat.starts_with("\x1b[30;48;5;236mS\x1b[30;48;5;196mz\x1b[30;48;5;46mE"));
     }
 }
@@ -1,111 +1,379 @@
-use core::panic;
 use std::{
+    cmp::Ordering,
+    env,
+    fmt::{self, Display, Formatter},
     io::{self, BufRead},
-    iter::{once, Peekable},
+    iter::once,
     ops::Deref,
+    slice,
+    str::FromStr,
 };
 
-#[derive(Debug)]
-enum ListOrItem {
-    List(Vec<ListOrItem>),
-    Item(i32),
-}
-
-impl ListOrItem {
-    fn parse(iter: &mut Peekable<impl Iterator<Item = char>>) -> ListOrItem {
-        match iter.next() {
-            Some('[') => {
-                let mut res = Vec::new();
-                while let Some(&c) = iter.peek() {
-                    if c == ']' {
-                        iter.next();
-                        break;
-                    } else if c == ',' {
-                        iter.next();
+use serde_json::Value;
+
+/// An integer or a list of packets, ordered the way the distress signal wants them.
+///
+/// Equality follows that order, so `[[1]]` equals `1`; compare the `Display` output to
+/// tell them apart.
+#[derive(Debug, Clone)]
+enum Packet {
+    Int(i64),
+    List(Vec<Packet>),
+}
+
+impl Ord for Packet {
+    fn cmp(&self, other: &Packet) -> Ordering {
+        match (self, other) {
+            (Packet::Int(a), Packet::Int(b)) => a.cmp(b),
+            (Packet::List(a), Packet::List(b)) => a.iter().cmp(b),
+            (Packet::Int(_), Packet::List(b)) => slice::from_ref(self).iter().cmp(b),
+            (Packet::List(a), Packet::Int(_)) => a.iter().cmp(slice::from_ref(other)),
+        }
+    }
+}
+
+impl PartialOrd for Packet {
+    fn partial_cmp(&self, other: &Packet) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Packet {
+    fn eq(&self, other: &Packet) -> bool {
+        self.cmp(other).is_eq()
+    }
+}
+
+impl Eq for Packet {}
+
+impl Display for Packet {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Packet::Int(i) => write!(f, "{i}"),
+            Packet::List(list) => {
+                f.write_str("[")?;
+                for (i, p) in list.iter().enumerate() {
+                    if i > 0 {
+                        f.write_str(",")?;
+                    }
+                    write!(f, "{p}")?;
+                }
+                f.write_str("]")
+            }
+        }
+    }
+}
+
+impl FromStr for Packet {
+    type Err = String;
+
+    /// Parses the puzzle's syntax, allowing whitespace between tokens like JSON does.
+    fn from_str(s: &str) -> Result<Packet, String> {
+        let mut parser = Parser { s, pos: 0 };
+        let packet = parser.packet()?;
+        parser.skip_whitespace();
+        match parser.peek() {
+            None => Ok(packet),
+            Some(_) => Err(parser.error("end of packet")),
+        }
+    }
+}
+
+struct Parser<'a> {
+    s: &'a str,
+    pos: usize,
+}
+
+impl Parser<'_> {
+    fn peek(&self) -> Option<u8> {
+        self.s.as_bytes().get(self.pos).copied()
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self.peek().is_some_and(|c| c.is_ascii_whitespace()) {
+            self.pos += 1;
+        }
+    }
+
+    fn error(&self, expected: &str) -> String {
+        match self.s[self.pos..].chars().next() {
+            Some(c) => format!("column {}: expected {expected}, found {c:?}", self.pos + 1),
+            None => format!(
+                "column {}: expected {expected}, found the end",
+                self.pos + 1
+            ),
+        }
+    }
+
+    fn packet(&mut self) -> Result<Packet, String> {
+        self.skip_whitespace();
+        match self.peek() {
+            Some(b'[') => {
+                self.pos += 1;
+                let mut list = vec![];
+                self.skip_whitespace();
+                if self.peek() == Some(b']') {
+                    self.pos += 1;
+                    return Ok(Packet::List(list));
+                }
+                loop {
+                    list.push(self.packet()?);
+                    self.skip_whitespace();
+                    match self.peek() {
+                        Some(b',') => self.pos += 1,
+                        Some(b']') => {
+                            self.pos += 1;
+                            return Ok(Packet::List(list));
+                        }
+                        _ => return Err(self.error("`,` or `]`")),
                     }
-                    res.push(Self::parse(iter));
                 }
-                return ListOrItem::List(res);
             }
-            Some(c) if c.is_digit(10) => {
-                let mut res = c.to_digit(10).unwrap();
-                while let Some(d) = iter.peek().and_then(|x| x.to_digit(10)) {
-                    res *= 10;
-                    res += d;
-                    iter.next();
+            Some(b'-' | b'0'..=b'9') => {
+                let start = self.pos;
+                self.pos += 1;
+                while self.peek().is_some_and(|c| c.is_ascii_digit()) {
+                    self.pos += 1;
                 }
-                ListOrItem::Item(res as i32)
+                self.s[start..self.pos]
+                    .parse()
+                    .map(Packet::Int)
+                    .map_err(|_| {
+                        self.pos = start;
+                        self.error("an integer")
+                    })
             }
-            c => panic!("Unexpected: {c:?}"),
+            _ => Err(self.error("a packet")),
         }
     }
 }
 
-impl PartialEq for ListOrItem {
-    fn eq(&self, other: &Self) -> bool {
-        if let Some(ord) = self.partial_cmp(other) {
-            ord.is_eq()
-        } else {
-            false
+impl From<&Packet> for Value {
+    fn from(packet: &Packet) -> Value {
+        match packet {
+            Packet::Int(i) => Value::from(*i),
+            Packet::List(list) => Value::Array(list.iter().map(Value::from).collect()),
         }
     }
 }
 
-impl Eq for ListOrItem {}
+impl TryFrom<&Value> for Packet {
+    type Error = String;
 
-impl Ord for ListOrItem {
-    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
-        self.partial_cmp(other).unwrap()
+    fn try_from(value: &Value) -> Result<Packet, String> {
+        match value {
+            Value::Number(n) => n
+                .as_i64()
+                .map(Packet::Int)
+                .ok_or_else(|| format!("{n} is not an integer packet")),
+            Value::Array(values) => values
+                .iter()
+                .map(Packet::try_from)
+                .collect::<Result<_, _>>()
+                .map(Packet::List),
+            other => Err(format!("{other} is not a packet")),
+        }
     }
 }
 
-impl PartialOrd for ListOrItem {
-    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
-        match self {
-            &ListOrItem::Item(i) => match other {
-                &ListOrItem::List(ref ol) => vec![ListOrItem::Item(i)].partial_cmp(&ol),
-                &ListOrItem::Item(oi) => i.partial_cmp(&oi),
-            },
-            &ListOrItem::List(ref l) => match other {
-                &ListOrItem::List(ref ol) => l.partial_cmp(ol),
-                &ListOrItem::Item(oi) => l.partial_cmp(&vec![ListOrItem::Item(oi)]),
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Side {
+    Left,
+    Right,
+}
+
+/// Why a comparison came out the way it did.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Reason {
+    LeftSmaller,
+    RightSmaller,
+    LeftRanOut,
+    RightRanOut,
+}
+
+#[derive(Debug, Clone)]
+enum StepKind {
+    Compare(Packet, Packet),
+    /// The integer on one side is wrapped in a list to compare it with the other side.
+    Convert(Side, Packet),
+    Decided(Reason),
+}
+
+/// One line of the comparison trace, nested `depth` levels deep.
+#[derive(Debug, Clone)]
+struct Step {
+    depth: usize,
+    kind: StepKind,
+}
+
+impl Display for Step {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{:1$}- ", "", self.depth * 2)?;
+        match &self.kind {
+            StepKind::Compare(left, right) => write!(f, "Compare {left} vs {right}"),
+            StepKind::Convert(side, list) => {
+                let side = if *side == Side::Left { "left" } else { "right" };
+                write!(
+                    f,
+                    "Mixed types; convert {side} to {list} and retry comparison"
+                )
+            }
+            StepKind::Decided(reason) => f.write_str(match reason {
+                Reason::LeftSmaller => "Left side is smaller, so inputs are in the right order",
+                Reason::RightSmaller => {
+                    "Right side is smaller, so inputs are not in the right order"
+                }
+                Reason::LeftRanOut => {
+                    "Left side ran out of items, so inputs are in the right order"
+                }
+                Reason::RightRanOut => {
+                    "Right side ran out of items, so inputs are not in the right order"
+                }
+            }),
+        }
+    }
+}
+
+/// The comparison of two packets, step by step, down to the element that decides it.
+#[derive(Debug, Clone)]
+struct Explanation {
+    steps: Vec<Step>,
+    order: Ordering,
+}
+
+impl Display for Explanation {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        for step in &self.steps {
+            writeln!(f, "{step}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Compares two packets like `Ord` does, recording each step the way the puzzle shows it.
+fn explain(left: &Packet, right: &Packet) -> Explanation {
+    fn compare(left: &Packet, right: &Packet, depth: usize, steps: &mut Vec<Step>) -> Ordering {
+        let compare_step = StepKind::Compare(left.clone(), right.clone());
+        steps.push(Step {
+            depth,
+            kind: compare_step,
+        });
+        let decided = |steps: &mut Vec<Step>, reason, order| {
+            steps.push(Step {
+                depth: depth + 1,
+                kind: StepKind::Decided(reason),
+            });
+            order
+        };
+        match (left, right) {
+            (Packet::Int(a), Packet::Int(b)) => match a.cmp(b) {
+                Ordering::Less => decided(steps, Reason::LeftSmaller, Ordering::Less),
+                Ordering::Greater => decided(steps, Reason::RightSmaller, Ordering::Greater),
+                Ordering::Equal => Ordering::Equal,
             },
+            (Packet::List(a), Packet::List(b)) => {
+                for i in 0.. {
+                    match (a.get(i), b.get(i)) {
+                        (None, None) => break,
+                        (None, Some(_)) => {
+                            return decided(steps, Reason::LeftRanOut, Ordering::Less)
+                        }
+                        (Some(_), None) => {
+                            return decided(steps, Reason::RightRanOut, Ordering::Greater)
+                        }
+                        (Some(l), Some(r)) => match compare(l, r, depth + 1, steps) {
+                            Ordering::Equal => (),
+                            order => return order,
+                        },
+                    }
+                }
+                Ordering::Equal
+            }
+            (Packet::Int(_), Packet::List(_)) => {
+                let converted = Packet::List(vec![left.clone()]);
+                let convert_step = StepKind::Convert(Side::Left, converted.clone());
+                steps.push(Step {
+                    depth: depth + 1,
+                    kind: convert_step,
+                });
+                compare(&converted, right, depth + 1, steps)
+            }
+            (Packet::List(_), Packet::Int(_)) => {
+                let converted = Packet::List(vec![right.clone()]);
+                let convert_step = StepKind::Convert(Side::Right, converted.clone());
+                steps.push(Step {
+                    depth: depth + 1,
+                    kind: convert_step,
+                });
+                compare(left, &converted, depth + 1, steps)
+            }
         }
     }
+
+    let mut steps = vec![];
+    let order = compare(left, right, 0, &mut steps);
+    Explanation { steps, order }
 }
 
-type Parsed = Vec<(ListOrItem, ListOrItem)>;
+type Parsed = Vec<(Packet, Packet)>;
 
 fn main() {
     let stdin = io::stdin();
     let lines = stdin.lock().lines().map(|x| x.unwrap());
-    let map = parse(lines);
-    println!("part1: {}", part1(&map));
-    println!("part2: {}", part2(&map));
-}
+    let map = parse(lines).unwrap_or_else(|e| panic!("{e}"));
 
-fn parse(lines: impl Iterator<Item = impl Deref<Target = str>>) -> Parsed {
-    let mut result = Parsed::new();
-    let mut lines = lines.peekable();
-    while let Some(_) = lines.peek() {
-        let one = lines.next().unwrap();
-        let mut one = one.chars().peekable();
-        let one = ListOrItem::parse(&mut one);
-        let two = lines.next().unwrap();
-        let mut two = two.chars().peekable();
-        let two = ListOrItem::parse(&mut two);
-        result.push((one, two));
-        lines.next();
+    let mut args = env::args().skip(1);
+    match args.next().as_deref() {
+        None => {
+            println!("part1: {}", part1(&map));
+            println!("part2: {}", part2(&map));
+        }
+        Some(mode @ ("decode" | "sorted")) => {
+            let mut dividers = args.map(|d| d.parse().unwrap()).collect::<Vec<_>>();
+            if dividers.is_empty() {
+                dividers = default_dividers();
+            }
+            if mode == "sorted" {
+                print!("{}", sorted_dump(&map, &dividers));
+            }
+            println!("decoder key: {}", decoder_key(&map, &dividers));
+        }
+        Some("explain") => {
+            let pair = args.next().map(|p| p.parse::<usize>().unwrap());
+            let mut right_order = vec![];
+            for (i, (left, right)) in map.iter().enumerate() {
+                if pair.is_none_or(|p| p == i + 1) {
+                    let explanation = explain(left, right);
+                    println!("== Pair {} ==\n{explanation}", i + 1);
+                    if explanation.order.is_lt() {
+                        right_order.push(i + 1);
+                    }
+                }
+            }
+            println!("in the right order: {right_order:?}");
+        }
+        Some(other) => panic!("Unknown command: {other}"),
     }
+}
 
-    result
-
-    // lines
-    //     .map(|line| {
-    //         let line: &str = &*line;
-    //         todo!();
-    //     })
-    //     .collect::<Parsed>()
+/// Parses pairs of packets on consecutive lines, separated by blank lines.
+fn parse(lines: impl Iterator<Item = impl Deref<Target = str>>) -> Result<Parsed, String> {
+    let mut packets = vec![];
+    for (i, line) in lines.enumerate() {
+        if line.trim().is_empty() {
+            if packets.len() % 2 == 1 {
+                return Err(format!("line {}: packet without a pair", i + 1));
+            }
+            continue;
+        }
+        let packet = line.parse::<Packet>();
+        packets.push(packet.map_err(|e| format!("line {}, {e}", i + 1))?);
+    }
+    if packets.len() % 2 == 1 {
+        return Err("last packet without a pair".to_owned());
+    }
+    let mut packets = packets.into_iter();
+    Ok(std::iter::from_fn(|| Some((packets.next()?, packets.next()?))).collect())
 }
 
 fn part1(parsed: &Parsed) -> usize {
@@ -117,27 +385,47 @@ fn part1(parsed: &Parsed) -> usize {
         .sum()
 }
 
-fn part2(parsed: &Parsed) -> usize {
-    let extra = parse(
-        "\
-    [[2]]
-    [[6]]
-    "
-        .lines()
-        .map(|l| l.trim()),
-    );
-    let mut signals = parsed
+/// `[[2]]` and `[[6]]`.
+fn default_dividers() -> Vec<Packet> {
+    [2, 6]
+        .map(|i| Packet::List(vec![Packet::List(vec![Packet::Int(i)])]))
+        .to_vec()
+}
+
+fn packets(parsed: &Parsed) -> impl Iterator<Item = &Packet> + Clone {
+    parsed.iter().flat_map(|(x, y)| once(x).chain(once(y)))
+}
+
+/// The product of the positions the dividers would have if they were sorted together with
+/// all the packets, counting from 1. Rather than sorting, each divider's position is one
+/// more than the number of packets and other dividers smaller than it.
+fn decoder_key(parsed: &Parsed, dividers: &[Packet]) -> usize {
+    dividers
         .iter()
-        .chain(extra.iter())
-        .flat_map(|(x, y)| once(x).chain(once(y)))
+        .map(|d| {
+            let smaller = packets(parsed).chain(dividers).filter(|&p| p < d).count();
+            smaller + 1
+        })
+        .product()
+}
+
+/// All packets and dividers in order, one per line, with the dividers marked.
+fn sorted_dump(parsed: &Parsed, dividers: &[Packet]) -> String {
+    let mut all = packets(parsed)
+        .map(|p| (p, false))
+        .chain(dividers.iter().map(|d| (d, true)))
         .collect::<Vec<_>>();
-    signals.sort();
-    let extra = extra.first().unwrap();
-    let (x, y) = (
-        signals.iter().position(|&x| *x == extra.0).unwrap(),
-        signals.iter().position(|&x| *x == extra.1).unwrap(),
-    );
-    (x + 1) * (y + 1)
+    all.sort();
+    all.into_iter()
+        .map(|(p, divider)| match divider {
+            true => format!("{p}  <- divider\n"),
+            false => format!("{p}\n"),
+        })
+        .collect()
+}
+
+fn part2(parsed: &Parsed) -> usize {
+    decoder_key(parsed, &default_dividers())
 }
 
 #[cfg(test)]
@@ -175,16 +463,158 @@ mod test {
 
     #[test]
     fn test_part1() {
-        let root = parse(sample());
-        for l in &root {
-            println!("x: {l:?}");
-        }
+        let root = parse(sample()).unwrap();
         assert_eq!(part1(&root), 13);
     }
 
     #[test]
     fn test_part2() {
-        let root = parse(sample());
+        let root = parse(sample()).unwrap();
         assert_eq!(part2(&root), 140);
     }
+
+    fn packet(s: &str) -> Packet {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn test_round_trip() {
+        for line in sample().filter(|l| !l.is_empty()) {
+            assert_eq!(packet(line).to_string(), line);
+            let json = Value::from(&packet(line));
+            assert_eq!(json.to_string(), line);
+            assert_eq!(Packet::try_from(&json).unwrap().to_string(), line);
+        }
+        assert_eq!(packet(" [ -1 , [] ,[ 20]] ").to_string(), "[-1,[],[20]]");
+        let json: Value = serde_json::from_str("[1, [-2, []]]").unwrap();
+        assert_eq!(Packet::try_from(&json).unwrap().to_string(), "[1,[-2,[]]]");
+        let json: Value = serde_json::from_str("[1.5]").unwrap();
+        assert!(Packet::try_from(&json).is_err());
+        let json: Value = serde_json::from_str("[\"a\"]").unwrap();
+        assert!(Packet::try_from(&json).is_err());
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        let error = |s: &str| s.parse::<Packet>().unwrap_err();
+        assert_eq!(
+            error("[1,2"),
+            "column 5: expected `,` or `]`, found the end"
+        );
+        assert_eq!(error("[1;2]"), "column 3: expected `,` or `]`, found ';'");
+        assert_eq!(error("[1,]"), "column 4: expected a packet, found ']'");
+        assert_eq!(error("[--1]"), "column 2: expected an integer, found '-'");
+        assert_eq!(error("[1]]"), "column 4: expected end of packet, found ']'");
+        assert_eq!(
+            parse(["[1]", "[x]"].into_iter()).unwrap_err(),
+            "line 2, column 2: expected a packet, found 'x'"
+        );
+        assert_eq!(
+            parse(["[1]", "", "[2]"].into_iter()).unwrap_err(),
+            "line 2: packet without a pair"
+        );
+    }
+
+    #[test]
+    fn test_order() {
+        assert!(packet("[-3]") < packet("[-2]"));
+        assert!(packet("[-1,5]") < packet("[[0]]"));
+        assert_eq!(packet("[[1]]"), packet("1"));
+        assert!(packet("[[4,4],4,4]") < packet("[[4,4],4,4,4]"));
+        assert!(packet("[]") < packet("-100"));
+        assert_eq!(packet("[[[]]]").cmp(&packet("[[]]")), Ordering::Greater);
+    }
+
+    /// Why the comparison was decided, or `None` if the packets are equal.
+    fn reason(explanation: &Explanation) -> Option<Reason> {
+        match explanation.steps.last()?.kind {
+            StepKind::Decided(reason) => Some(reason),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn test_explain() {
+        let root = parse(sample()).unwrap();
+        let explanation = explain(&root[1].0, &root[1].1);
+        assert_eq!(explanation.order, Ordering::Less);
+        assert_eq!(reason(&explanation), Some(Reason::LeftSmaller));
+        assert_eq!(
+            explanation.to_string(),
+            "\
+- Compare [[1],[2,3,4]] vs [[1],4]
+  - Compare [1] vs [1]
+    - Compare 1 vs 1
+  - Compare [2,3,4] vs 4
+    - Mixed types; convert right to [4] and retry comparison
+    - Compare [2,3,4] vs [4]
+      - Compare 2 vs 4
+        - Left side is smaller, so inputs are in the right order
+"
+        );
+
+        let reasons = root
+            .iter()
+            .map(|(l, r)| reason(&explain(l, r)).unwrap())
+            .collect::<Vec<_>>();
+        use Reason::*;
+        assert_eq!(
+            reasons,
+            [
+                LeftSmaller,
+                LeftSmaller,
+                RightSmaller,
+                LeftRanOut,
+                RightRanOut,
+                LeftRanOut,
+                RightRanOut,
+                RightSmaller
+            ]
+        );
+        let explanation = explain(&root[2].0, &root[2].1);
+        assert!(matches!(
+            explanation.steps[2].kind,
+            StepKind::Convert(Side::Left, _)
+        ));
+
+        let same = explain(&packet("[1,[2]]"), &packet("[[1],2]"));
+        assert_eq!((same.order, reason(&same)), (Ordering::Equal, None));
+        for (l, r) in &root {
+            assert_eq!(explain(l, r).order, l.cmp(r));
+            assert_eq!(explain(r, l).order, r.cmp(l));
+        }
+    }
+
+    /// The original decoder: sort everything and look the dividers up.
+    fn sorted_decoder_key(parsed: &Parsed, dividers: &[Packet]) -> usize {
+        let mut signals = packets(parsed).chain(dividers).collect::<Vec<_>>();
+        signals.sort();
+        dividers
+            .iter()
+            .map(|d| signals.iter().position(|&x| x == d).unwrap() + 1)
+            .product()
+    }
+
+    #[test]
+    fn test_decoder_key() {
+        let root = parse(sample()).unwrap();
+        assert_eq!(decoder_key(&root, &default_dividers()), 140);
+        let dump = sorted_dump(&root, &default_dividers());
+        assert_eq!(dump.lines().count(), 18);
+        assert_eq!(dump.lines().nth(9), Some("[[2]]  <- divider"));
+        assert_eq!(dump.lines().nth(13), Some("[[6]]  <- divider"));
+
+        for dividers in [
+            &["[[2]]"][..],
+            &["[[6]]", "[[2]]"],
+            &["[]", "[10]", "[5,5]", "[[1],[-1]]"],
+        ] {
+            let dividers = dividers.iter().map(|d| packet(d)).collect::<Vec<_>>();
+            assert_eq!(
+                decoder_key(&root, &dividers),
+                sorted_decoder_key(&root, &dividers),
+                "{dividers:?}"
+            );
+        }
+    }
 }
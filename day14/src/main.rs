@@ -1,16 +1,26 @@
 use std::{
+    env,
     io::{self, BufRead},
-    iter::once,
 };
 
-type Parsed = Vec<Vec<(usize, usize)>>;
+type Point = (i64, i64);
+type Parsed = Vec<Vec<Point>>;
+
+const SOURCE: Point = (500, 0);
 
 fn main() {
     let stdin = io::stdin();
     let lines = stdin.lock().lines().map(|x| x.unwrap());
     let map = parse(lines);
     println!("part1: {}", part1(&map));
-    println!("part2: {}", part2(&map));
+    match env::args().nth(1).as_deref() {
+        None => println!("part2: {}", part2(&map)),
+        Some("fill") => println!(
+            "part2: {}",
+            Cave::new(&map, SOURCE, true).fill_count(SOURCE)
+        ),
+        Some(other) => panic!("Unknown command: {other}"),
+    }
 }
 
 fn parse(lines: impl Iterator<Item = String>) -> Parsed {
@@ -18,105 +28,153 @@ fn parse(lines: impl Iterator<Item = String>) -> Parsed {
         .map(|line| {
             line.split(" -> ")
                 .map(|x| x.split_once(',').unwrap())
-                .map(|(x, y)| (x.parse::<usize>().unwrap(), y.parse::<usize>().unwrap()))
+                .map(|(x, y)| (x.parse::<i64>().unwrap(), y.parse::<i64>().unwrap()))
                 .collect::<Vec<_>>()
         })
         .collect::<Parsed>()
 }
 
-fn creep(creeper: &mut usize, dest: usize) {
-    if *creeper < dest {
-        *creeper += 1;
-    } else if *creeper > dest {
-        *creeper -= 1;
-    }
+/// Every point on a path of straight rock lines.
+fn rock_points(line: &[Point]) -> impl Iterator<Item = Point> + '_ {
+    let corners = line.iter().take(1).copied();
+    let segments = line.windows(2).flat_map(|w| {
+        let ((x, y), (nx, ny)) = (w[0], w[1]);
+        let (dx, dy) = ((nx - x).signum(), (ny - y).signum());
+        let len = (nx - x).abs().max((ny - y).abs());
+        (1..=len).map(move |i| (x + i * dx, y + i * dy))
+    });
+    corners.chain(segments)
 }
 
-fn part1(parsed: &Parsed) -> usize {
-    let mut map = vec![vec![false; 1000]; 1000];
-
-    // Paint lines
-    for line in parsed {
-        let mut iter = line.iter();
-        let mut cur = iter.next().unwrap().to_owned();
-        for next in iter {
-            while cur != *next {
-                map[cur.0][cur.1] = true;
-                creep(&mut cur.0, next.0);
-                creep(&mut cur.1, next.1);
-                map[cur.0][cur.1] = true;
-            }
+/// The cave as a grid of blocked cells, just large enough for anything sand can reach from
+/// `source`. Below the lowest rock there is either nothing or a floor two further down
+/// that stretches out forever; sand can spread at most one column per row, so the floor
+/// never needs to be wider than the grid.
+struct Cave {
+    min_x: i64,
+    min_y: i64,
+    width: usize,
+    cells: Vec<bool>,
+    lowest_rock: i64,
+    floor: Option<i64>,
+}
+
+impl Cave {
+    fn new(rocks: &Parsed, source: Point, floor: bool) -> Cave {
+        let points = || rocks.iter().flatten().chain([&source]);
+        // Rocks above the source can't be reached, so they don't move the abyss or the floor.
+        let lowest_rock = rocks.iter().flatten().map(|p| p.1).fold(source.1, i64::max);
+        let floor = floor.then_some(lowest_rock + 2);
+        // Sand never comes to rest below the floor or, without one, the lowest rock.
+        let depth = floor.unwrap_or(lowest_rock + 1) - source.1;
+        let min_x = points().map(|p| p.0).min().unwrap().min(source.0 - depth) - 1;
+        let max_x = points().map(|p| p.0).max().unwrap().max(source.0 + depth) + 1;
+        let width = (max_x - min_x + 1) as usize;
+        let min_y = points().map(|p| p.1).min().unwrap();
+        let height = (floor.unwrap_or(lowest_rock) - min_y + 1) as usize;
+        let mut cave = Cave {
+            min_x,
+            min_y,
+            width,
+            cells: vec![false; width * height],
+            lowest_rock,
+            floor,
+        };
+        for point in rocks.iter().flat_map(|line| rock_points(line)) {
+            cave.fill(point);
         }
+        cave
     }
 
-    let mut count = 0;
-    'outer: loop {
-        let mut sand = (500, 0);
-
-        while sand.1 < 1000 - 1 {
-            if !map[sand.0][sand.1 + 1] {
-                sand = (sand.0, sand.1 + 1);
-            } else if !map[sand.0 - 1][sand.1 + 1] {
-                sand = (sand.0 - 1, sand.1 + 1)
-            } else if !map[sand.0 + 1][sand.1 + 1] {
-                sand = (sand.0 + 1, sand.1 + 1)
-            } else {
-                map[sand.0][sand.1] = true;
-                count += 1;
-                continue 'outer;
-            }
-        }
-        break;
+    fn index(&self, (x, y): Point) -> Option<usize> {
+        let column = usize::try_from(x - self.min_x)
+            .ok()
+            .filter(|&c| c < self.width)?;
+        let index = usize::try_from(y - self.min_y).ok()? * self.width + column;
+        (index < self.cells.len()).then_some(index)
     }
-    count
-}
 
-fn part2(parsed: &Parsed) -> usize {
-    let mut map = vec![vec![false; 1000]; 1000];
-
-    let floory = parsed
-        .iter()
-        .flat_map(|x| x.iter().map(|(_, y)| y))
-        .max()
-        .unwrap()
-        + 2;
-    let floor = vec![(0, floory), (999, floory)];
-    let parsed = parsed.iter().chain(once(&floor));
-
-    // Paint lines
-    for line in parsed {
-        let mut iter = line.iter();
-        let mut cur = iter.next().unwrap().to_owned();
-        for next in iter {
-            while cur != *next {
-                map[cur.0][cur.1] = true;
-                creep(&mut cur.0, next.0);
-                creep(&mut cur.1, next.1);
-                map[cur.0][cur.1] = true;
+    fn blocked(&self, p: Point) -> bool {
+        Some(p.1) == self.floor || self.index(p).is_some_and(|i| self.cells[i])
+    }
+
+    fn fill(&mut self, p: Point) {
+        let i = self
+            .index(p)
+            .unwrap_or_else(|| panic!("{p:?} is outside the cave"));
+        self.cells[i] = true;
+    }
+
+    /// Whether sand at `p` will fall forever.
+    fn in_abyss(&self, p: Point) -> bool {
+        self.floor.is_none() && p.1 > self.lowest_rock
+    }
+
+    /// The cells below `p` that sand tries, in order.
+    fn below(p: Point) -> [Point; 3] {
+        [0, -1, 1].map(|dx| (p.0 + dx, p.1 + 1))
+    }
+
+    /// Pours sand from `source` until it stops coming to rest and returns how many grains
+    /// did.
+    ///
+    /// Every grain follows the previous one's path down to where that one came to rest, so
+    /// instead of starting each from the source, the path is kept and the next grain
+    /// starts from the last cell before it.
+    fn pour(&mut self, source: Point) -> usize {
+        let mut count = 0;
+        let mut path = vec![];
+        if !self.blocked(source) {
+            path.push(source);
+        }
+        while let Some(&p) = path.last() {
+            if self.in_abyss(p) {
+                break;
+            }
+            match Cave::below(p).into_iter().find(|&n| !self.blocked(n)) {
+                Some(n) => path.push(n),
+                None => {
+                    self.fill(p);
+                    count += 1;
+                    path.pop();
+                }
             }
         }
+        count
     }
 
-    let mut count = 0;
-    'outer: loop {
-        let mut sand = (500, 0);
-
-        while !map[500][0] {
-            if !map[sand.0][sand.1 + 1] {
-                sand = (sand.0, sand.1 + 1);
-            } else if !map[sand.0 - 1][sand.1 + 1] {
-                sand = (sand.0 - 1, sand.1 + 1)
-            } else if !map[sand.0 + 1][sand.1 + 1] {
-                sand = (sand.0 + 1, sand.1 + 1)
-            } else {
-                map[sand.0][sand.1] = true;
-                count += 1;
-                continue 'outer;
-            }
+    /// How many grains a cave with a floor takes before `source` is blocked, without
+    /// pouring: exactly the cells that can be reached from `source` moving down, down-left
+    /// or down-right without going through rock end up filled, so those are counted one
+    /// row at a time.
+    fn fill_count(&self, source: Point) -> usize {
+        let floor = self.floor.expect("only a cave with a floor fills up");
+        let column = |x: i64| (x - self.min_x) as usize;
+        let mut row = vec![false; self.width];
+        row[column(source.0)] = !self.blocked(source);
+        let mut count = row.iter().filter(|&&r| r).count();
+        for y in source.1 + 1..floor {
+            let above = row;
+            row = (0..self.width)
+                .map(|c| {
+                    let x = self.min_x + c as i64;
+                    let from_above = (c.saturating_sub(1)..=c + 1)
+                        .any(|a| above.get(a).copied().unwrap_or(false));
+                    from_above && !self.blocked((x, y))
+                })
+                .collect();
+            count += row.iter().filter(|&&r| r).count();
         }
-        break;
+        count
     }
-    count
+}
+
+fn part1(parsed: &Parsed) -> usize {
+    Cave::new(parsed, SOURCE, false).pour(SOURCE)
+}
+
+fn part2(parsed: &Parsed) -> usize {
+    Cave::new(parsed, SOURCE, true).pour(SOURCE)
 }
 
 #[cfg(test)]
@@ -142,4 +200,100 @@ mod test {
         let root = parse(sample());
         assert_eq!(part2(&root), 93);
     }
+
+    #[test]
+    fn test_unbounded() {
+        // Rocks near x = 0 and past 1000 used to be out of bounds.
+        let far = parse(
+            ["0,2 -> 2,2", "1498,60 -> 1500,60"]
+                .map(String::from)
+                .into_iter(),
+        );
+        let cave = Cave::new(&far, (1, 0), false);
+        assert!(cave.blocked((0, 2)) && cave.blocked((1500, 60)));
+        assert_eq!(Cave::new(&far, (1, 0), false).pour((1, 0)), 1);
+        assert_eq!(part1(&far), 0);
+
+        // With the floor at 62, sand fills the whole triangle above it.
+        assert_eq!(part2(&far), 62 * 62);
+
+        let single = parse(["500,1 -> 500,1"].map(String::from).into_iter());
+        assert_eq!(part1(&single), 0);
+        assert_eq!(part2(&single), 9 - 1);
+
+        // Rocks above the source are out of reach, but still have to fit in the grid.
+        let above = parse(
+            ["490,-5 -> 510,-5", "500,1 -> 500,1"]
+                .map(String::from)
+                .into_iter(),
+        );
+        let cave = Cave::new(&above, SOURCE, false);
+        assert!(cave.blocked((490, -5)) && cave.blocked((510, -5)));
+        assert_eq!(part1(&above), 0);
+        assert_eq!(part2(&above), 9 - 1);
+        assert_eq!(Cave::new(&above, SOURCE, true).fill_count(SOURCE), 9 - 1);
+        let only_above = parse(["500,-3 -> 500,-1"].map(String::from).into_iter());
+        assert_eq!(part1(&only_above), 0);
+        assert_eq!(part2(&only_above), 4);
+    }
+
+    // The original algorithm, which drops every grain from the source.
+
+    /// Lets one grain of sand fall from `source`. Returns where it came to rest, or `None`
+    /// if it fell into the abyss or the source is already blocked.
+    fn drop_grain(cave: &mut Cave, source: Point) -> Option<Point> {
+        if cave.blocked(source) {
+            return None;
+        }
+        let mut p = source;
+        loop {
+            if cave.in_abyss(p) {
+                return None;
+            }
+            match Cave::below(p).into_iter().find(|&n| !cave.blocked(n)) {
+                Some(n) => p = n,
+                None => {
+                    cave.fill(p);
+                    return Some(p);
+                }
+            }
+        }
+    }
+
+    fn naive_pour(cave: &mut Cave, source: Point) -> usize {
+        let mut count = 0;
+        while drop_grain(cave, source).is_some() {
+            count += 1;
+        }
+        count
+    }
+
+    #[test]
+    fn test_pour_matches_naive() {
+        let root = parse(sample());
+        assert_eq!(Cave::new(&root, SOURCE, true).fill_count(SOURCE), 93);
+
+        let caves = [
+            SAMPLE_INPUT,
+            "",
+            "500,2 -> 500,2",
+            "495,5 -> 505,5",
+            "490,3 -> 499,3\n501,3 -> 510,3",
+            "498,2 -> 498,6 -> 502,6 -> 502,2",
+            "497,9 -> 503,9\n499,4 -> 501,4\n494,7 -> 495,7 -> 495,12",
+            "496,1 -> 496,8\n504,1 -> 504,8\n500,-2 -> 500,-1\n499,5 -> 501,5",
+        ];
+        for rocks in caves {
+            let rocks = parse(rocks.lines().map(|x| x.trim().to_owned()));
+            for floor in [false, true] {
+                let naive = naive_pour(&mut Cave::new(&rocks, SOURCE, floor), SOURCE);
+                let mut cave = Cave::new(&rocks, SOURCE, floor);
+                assert_eq!(cave.pour(SOURCE), naive, "{rocks:?}");
+                if floor {
+                    assert_eq!(Cave::new(&rocks, SOURCE, true).fill_count(SOURCE), naive);
+                    assert!(cave.blocked(SOURCE));
+                }
+            }
+        }
+    }
 }
@@ -1,90 +1,577 @@
-use std::{io, str::{FromStr, from_utf8}, fmt::Debug};
+use std::{
+    env,
+    fmt::{self, Debug, Display},
+    io::{self, Read},
+    str::FromStr,
+};
 
-fn eprintlnstacks(stacks: &Vec<Vec<u8>>) {
-    for s in stacks {
-        eprintln!("{}", from_utf8(s).unwrap())
-    }
-    eprintln!()
-}
+type Crate = String;
+type Stacks = Vec<Vec<Crate>>;
 
-fn borrow_two_mut<'vec, 'a, 'b, T>(vec: &'vec mut Vec<T>, indexOne: usize, indexTwo: usize) -> (&'a mut T, &'b mut T) where 'vec : 'a, 'vec : 'b, T: Debug {
-    assert!(indexOne != indexTwo);
-    let (lower, upper) = vec.split_at_mut(
-        usize::max(indexOne, indexTwo)
-    );
-    if indexOne < indexTwo {
-        (&mut lower[indexOne], &mut upper[0])
+fn borrow_two_mut<'vec, 'a, 'b, T>(
+    vec: &'vec mut [T],
+    index_one: usize,
+    index_two: usize,
+) -> (&'a mut T, &'b mut T)
+where
+    'vec: 'a,
+    'vec: 'b,
+    T: Debug,
+{
+    assert!(index_one != index_two);
+    let (lower, upper) = vec.split_at_mut(usize::max(index_one, index_two));
+    if index_one < index_two {
+        (&mut lower[index_one], &mut upper[0])
     } else {
-        (&mut upper[0], &mut lower[indexTwo])
+        (&mut upper[0], &mut lower[index_two])
     }
 }
 
-fn main() {
-    let stdin = io::stdin();
-    let mut input = String::new();
-    let mut stacks = Vec::<Vec::<u8>>::new();
+/// A single `move <count> from <from> to <to>` instruction, with 0-based stack indices.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Move {
+    count: usize,
+    from: usize,
+    to: usize,
+}
+
+impl FromStr for Move {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let words = s.split_whitespace().collect::<Vec<_>>();
+        let number = |word: &str| {
+            word.parse::<usize>()
+                .map_err(|_| format!("Expected a number, got {word:?} in {s:?}"))
+        };
+        match words[..] {
+            ["move", count, "from", from, "to", to] => {
+                let stack = |word| match number(word)? {
+                    0 => Err(format!("Stacks are numbered from 1 in {s:?}")),
+                    n => Ok(n - 1),
+                };
+                Ok(Move {
+                    count: number(count)?,
+                    from: stack(from)?,
+                    to: stack(to)?,
+                })
+            }
+            _ => Err(format!("Unexpected move: {s:?}")),
+        }
+    }
+}
 
-    stdin.read_line(&mut input).unwrap();
-    for _ in 0..(input.len() / 4) {
-        stacks.push(Vec::new())
+impl Display for Move {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "move {} from {} to {}",
+            self.count,
+            self.from + 1,
+            self.to + 1
+        )
     }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum MoveError {
+    NoSuchStack {
+        index: usize,
+        mv: Move,
+        stack: usize,
+    },
+    SameStack {
+        index: usize,
+        mv: Move,
+    },
+    NotEnoughCrates {
+        index: usize,
+        mv: Move,
+        available: usize,
+    },
+    PastEnd {
+        count: usize,
+        moves: usize,
+    },
+}
 
-    'outer: loop {
-        for (stack, cargo) in stacks.iter_mut().zip(input.bytes().skip(1).step_by(4)) {
-            if cargo.is_ascii_digit() {
-                break 'outer;
+impl Display for MoveError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MoveError::NoSuchStack { index, mv, stack } => {
+                write!(
+                    f,
+                    "move #{} ({mv}): there is no stack {}",
+                    index + 1,
+                    stack + 1
+                )
             }
-            if !cargo.is_ascii_whitespace() {
-                stack.push(cargo);
+            MoveError::SameStack { index, mv } => {
+                write!(
+                    f,
+                    "move #{} ({mv}): source and destination are the same",
+                    index + 1
+                )
+            }
+            MoveError::NotEnoughCrates {
+                index,
+                mv,
+                available,
+            } => write!(
+                f,
+                "move #{} ({mv}): stack {} only holds {available} crates",
+                index + 1,
+                mv.from + 1
+            ),
+            MoveError::PastEnd { count, moves } => {
+                write!(f, "cannot apply {count} moves, there are only {moves}")
+            }
+        }
+    }
+}
+
+/// A crane model; `lift` moves the top `count` crates of `from` onto `to`.
+///
+/// Callers guarantee that `from` holds at least `count` crates.
+trait Crane {
+    fn lift(&self, count: usize, from: &mut Vec<Crate>, to: &mut Vec<Crate>);
+}
+
+/// Moves one crate at a time, reversing their order.
+struct CrateMover9000;
+
+impl Crane for CrateMover9000 {
+    fn lift(&self, count: usize, from: &mut Vec<Crate>, to: &mut Vec<Crate>) {
+        for _ in 0..count {
+            to.push(from.pop().unwrap());
+        }
+    }
+}
+
+/// Moves all crates at once, keeping their order.
+struct CrateMover9001;
+
+impl Crane for CrateMover9001 {
+    fn lift(&self, count: usize, from: &mut Vec<Crate>, to: &mut Vec<Crate>) {
+        let from_len = from.len();
+        to.extend(from.drain(from_len - count..));
+    }
+}
+
+/// Moves at most `capacity` crates at once, keeping the order within each batch.
+struct BatchCrane {
+    capacity: usize,
+}
+
+impl Crane for BatchCrane {
+    fn lift(&self, mut count: usize, from: &mut Vec<Crate>, to: &mut Vec<Crate>) {
+        while count > 0 {
+            let batch = usize::min(count, self.capacity);
+            CrateMover9001.lift(batch, from, to);
+            count -= batch;
+        }
+    }
+}
+
+/// Parses `9000`, `9001` or `batch:<capacity>` into a crane.
+fn crane_by_name(name: &str) -> Option<Box<dyn Crane>> {
+    match name {
+        "9000" | "cratemover9000" => Some(Box::new(CrateMover9000)),
+        "9001" | "cratemover9001" => Some(Box::new(CrateMover9001)),
+        _ => {
+            let capacity = name.strip_prefix("batch:")?.parse().ok()?;
+            (capacity > 0).then(|| Box::new(BatchCrane { capacity }) as Box<dyn Crane>)
+        }
+    }
+}
+
+/// Runs a list of moves, one step at a time, with the ability to go back.
+struct Simulation<'a> {
+    crane: &'a dyn Crane,
+    moves: &'a [Move],
+    stacks: Stacks,
+    /// For every applied move, the crates as they were on top of the source stack.
+    history: Vec<Vec<Crate>>,
+}
+
+impl<'a> Simulation<'a> {
+    fn new(crane: &'a dyn Crane, stacks: Stacks, moves: &'a [Move]) -> Self {
+        Simulation {
+            crane,
+            moves,
+            stacks,
+            history: Vec::new(),
+        }
+    }
+
+    /// The number of moves applied so far.
+    fn position(&self) -> usize {
+        self.history.len()
+    }
+
+    fn validate(&self, index: usize, mv: Move) -> Result<(), MoveError> {
+        for stack in [mv.from, mv.to] {
+            if stack >= self.stacks.len() {
+                return Err(MoveError::NoSuchStack { index, mv, stack });
             }
         }
-        input.clear();
-        stdin.read_line(&mut input).unwrap();
+        if mv.from == mv.to {
+            return Err(MoveError::SameStack { index, mv });
+        }
+        let available = self.stacks[mv.from].len();
+        if available < mv.count {
+            return Err(MoveError::NotEnoughCrates {
+                index,
+                mv,
+                available,
+            });
+        }
+        Ok(())
+    }
+
+    /// Applies the next move. Returns `Ok(false)` once all moves have been applied.
+    fn step(&mut self) -> Result<bool, MoveError> {
+        let index = self.position();
+        let Some(&mv) = self.moves.get(index) else {
+            return Ok(false);
+        };
+        self.validate(index, mv)?;
+        let (from, to) = borrow_two_mut(&mut self.stacks, mv.from, mv.to);
+        self.history.push(from[from.len() - mv.count..].to_vec());
+        self.crane.lift(mv.count, from, to);
+        Ok(true)
     }
 
-    // Drop the empty line
-    stdin.read_line(&mut input).unwrap();
-    input.clear();
+    /// Reverts the last applied move. Returns `false` if there was nothing to undo.
+    fn undo(&mut self) -> bool {
+        let Some(lifted) = self.history.pop() else {
+            return false;
+        };
+        let mv = self.moves[self.position()];
+        let (from, to) = borrow_two_mut(&mut self.stacks, mv.from, mv.to);
+        to.truncate(to.len() - mv.count);
+        from.extend(lifted);
+        true
+    }
 
-    for stack in &mut stacks {
-        stack.reverse();
+    /// Rewinds or fast-forwards so that exactly the first `count` moves are applied.
+    fn seek(&mut self, count: usize) -> Result<(), MoveError> {
+        if count > self.moves.len() {
+            return Err(MoveError::PastEnd {
+                count,
+                moves: self.moves.len(),
+            });
+        }
+        while self.position() > count {
+            self.undo();
+        }
+        while self.position() < count {
+            self.step()?;
+        }
+        Ok(())
     }
 
-    eprintlnstacks(&stacks);
+    fn run(&mut self) -> Result<(), MoveError> {
+        self.seek(self.moves.len())
+    }
 
-    while stdin.read_line(&mut input).unwrap() != 0 {
-        let words = input.trim().split(' ').take(6).collect::<Vec::<_>>();
-        // eprintln!("{words:?}");
-        let count = words[1].parse::<usize>().unwrap();
-        let from = words[3].parse::<usize>().unwrap() - 1;
-        let to = words[5].parse::<usize>().unwrap() - 1;
+    /// The top crate of every stack, with `-` standing in for an empty stack.
+    fn tops(&self) -> String {
+        self.stacks
+            .iter()
+            .map(|s| s.last().map_or("-", String::as_str))
+            .collect()
+    }
+}
 
-        let (from, to) = borrow_two_mut(&mut stacks, from, to);
-        
-        cratemover9001(count, from, to);
-        
-        eprintlnstacks(&stacks);
+/// A whitespace-delimited token on a line of the drawing, with its byte span.
+struct Token<'a> {
+    text: &'a str,
+    start: usize,
+    end: usize,
+}
 
-        input.clear();
+impl Token<'_> {
+    fn overlaps(&self, other: &Token) -> bool {
+        self.start < other.end && other.start < self.end
     }
+}
 
-    eprintlnstacks(&stacks);
+fn tokens(line: &str) -> impl Iterator<Item = Token<'_>> {
+    line.split_whitespace().map(move |text| {
+        let start = text.as_ptr() as usize - line.as_ptr() as usize;
+        Token {
+            text,
+            start,
+            end: start + text.len(),
+        }
+    })
+}
+
+fn is_label_row(line: &str) -> bool {
+    let mut tokens = tokens(line).peekable();
+    tokens.peek().is_some() && tokens.all(|t| t.text.bytes().all(|b| b.is_ascii_digit()))
+}
 
-    for s in stacks {
-        print!("{}", *s.last().unwrap() as char);
+/// Parses the crate drawing up to and including the row of stack labels.
+///
+/// Crates are assigned to the stack whose label they sit above, so rows may be ragged and both
+/// labels and crate names may be wider than one character.
+fn parse_stacks<'a>(lines: &mut impl Iterator<Item = &'a str>) -> Result<Stacks, String> {
+    let mut rows = Vec::new();
+    let labels = loop {
+        let Some(line) = lines.next() else {
+            return Err("Missing the row of stack labels".to_owned());
+        };
+        if is_label_row(line) {
+            break tokens(line).collect::<Vec<_>>();
+        }
+        rows.push(line);
+    };
+
+    for (i, label) in labels.iter().enumerate() {
+        if label.text.parse() != Ok(i + 1) {
+            return Err(format!(
+                "Line {}: expected stack label {}, got {:?}",
+                rows.len() + 1,
+                i + 1,
+                label.text
+            ));
+        }
     }
-    println!();
+
+    let mut stacks = vec![Vec::new(); labels.len()];
+    for (line_no, row) in rows.iter().enumerate().rev() {
+        let depth = rows.len() - 1 - line_no;
+        for token in tokens(row) {
+            let name = token
+                .text
+                .strip_prefix('[')
+                .and_then(|t| t.strip_suffix(']'))
+                .filter(|name| !name.is_empty())
+                .ok_or_else(|| format!("Line {}: unexpected {:?}", line_no + 1, token.text))?;
+            let stack = labels
+                .iter()
+                .position(|label| label.overlaps(&token))
+                .ok_or_else(|| format!("Line {}: [{name}] is not above any label", line_no + 1))?;
+            if stacks[stack].len() != depth {
+                return Err(format!(
+                    "Line {}: [{name}] is floating in mid-air",
+                    line_no + 1
+                ));
+            }
+            stacks[stack].push(name.to_owned());
+        }
+    }
+    Ok(stacks)
 }
 
+/// Draws the stacks in the same format the puzzle input uses, including the label row.
+fn render(stacks: &Stacks) -> String {
+    let width = stacks
+        .iter()
+        .flatten()
+        .map(|name| name.len() + 2)
+        .chain([3, stacks.len().to_string().len()])
+        .max()
+        .unwrap();
+    let height = stacks.iter().map(Vec::len).max().unwrap_or(0);
+    let cell = |text: &str| {
+        let pad = width - text.len();
+        format!("{}{text}{}", " ".repeat(pad / 2), " ".repeat(pad - pad / 2))
+    };
 
-fn cratemover9000(count: usize, from: &mut Vec<u8>, to: &mut Vec<u8>) {
-    for _ in 0..count {
-        to.push(from.pop().unwrap());
+    let mut rows = Vec::new();
+    for level in (0..height).rev() {
+        let row = stacks.iter().map(|stack| match stack.get(level) {
+            Some(name) => cell(&format!("[{name}]")),
+            None => cell(""),
+        });
+        rows.push(row.collect::<Vec<_>>().join(" "));
     }
+    let labels = (1..=stacks.len()).map(|i| cell(&i.to_string()));
+    rows.push(labels.collect::<Vec<_>>().join(" "));
+
+    let mut result = rows.join("\n");
+    result.push('\n');
+    result
+}
+
+fn parse(input: &str) -> (Stacks, Vec<Move>) {
+    let mut lines = input.lines();
+    let stacks = parse_stacks(&mut lines).unwrap_or_else(|e| panic!("{e}"));
+    let moves = lines
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| line.parse::<Move>().unwrap_or_else(|e| panic!("{e}")))
+        .collect();
+    (stacks, moves)
 }
 
-fn cratemover9001(count: usize, from: &mut Vec<u8>, to: &mut Vec<u8>) {
-    let from_len = from.len();
-    to.extend(from.drain(from_len - count ..));
+fn main() {
+    // With `--steps`, the stacks are drawn after every move instead of only at the end.
+    let steps = env::args().any(|arg| arg == "--steps");
+    let mut args = env::args().skip(1).filter(|arg| arg != "--steps");
+    let crane = match args.next() {
+        Some(name) => crane_by_name(&name).unwrap_or_else(|| panic!("Unknown crane: {name}")),
+        None => Box::new(CrateMover9001),
+    };
+
+    let mut input = String::new();
+    io::stdin().read_to_string(&mut input).unwrap();
+    let (stacks, moves) = parse(&input);
+
+    let mut simulation = Simulation::new(&*crane, stacks, &moves);
+    let count = args.next().map(|prefix| {
+        prefix
+            .parse()
+            .unwrap_or_else(|e| panic!("Invalid move count {prefix:?}: {e}"))
+    });
+    eprintln!("{}", render(&simulation.stacks));
+    if steps {
+        while simulation.position() < count.unwrap_or(moves.len()) {
+            simulation
+                .seek(simulation.position() + 1)
+                .unwrap_or_else(|e| panic!("{e}"));
+            eprintln!("{}", render(&simulation.stacks));
+        }
+    } else {
+        match count {
+            Some(count) => simulation.seek(count),
+            None => simulation.run(),
+        }
+        .unwrap_or_else(|e| panic!("{e}"));
+        eprintln!("{}", render(&simulation.stacks));
+    }
+
+    println!("{}", simulation.tops());
 }
 
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    static SAMPLE_INPUT: &str = include_str!("../sample.txt");
+
+    fn run(crane: &dyn Crane) -> String {
+        let (stacks, moves) = parse(SAMPLE_INPUT);
+        let mut simulation = Simulation::new(crane, stacks, &moves);
+        simulation.run().unwrap();
+        simulation.tops()
+    }
+
+    #[test]
+    fn test_cranes() {
+        assert_eq!(run(&CrateMover9000), "CMZ");
+        assert_eq!(run(&CrateMover9001), "MCD");
+        assert_eq!(run(&BatchCrane { capacity: 1 }), "CMZ");
+        assert_eq!(run(&BatchCrane { capacity: 3 }), "MCD");
+        assert_eq!(run(&BatchCrane { capacity: 2 }), "MCZ");
+    }
+
+    #[test]
+    fn test_crane_by_name() {
+        assert!(crane_by_name("9000").is_some());
+        assert!(crane_by_name("batch:4").is_some());
+        assert!(crane_by_name("batch:0").is_none());
+        assert!(crane_by_name("9002").is_none());
+    }
+
+    #[test]
+    fn test_seek() {
+        let (stacks, moves) = parse(SAMPLE_INPUT);
+        let mut simulation = Simulation::new(&CrateMover9000, stacks.clone(), &moves);
+        simulation.run().unwrap();
+        simulation.seek(1).unwrap();
+        assert_eq!(
+            render(&simulation.stacks),
+            "\
+[D]        
+[N] [C]    
+[Z] [M] [P]
+ 1   2   3 
+"
+        );
+        simulation.seek(0).unwrap();
+        assert_eq!(simulation.stacks, stacks);
+        assert!(!simulation.undo());
+
+        let err = simulation.seek(5).unwrap_err();
+        assert_eq!(err, MoveError::PastEnd { count: 5, moves: 4 });
+        assert_eq!(err.to_string(), "cannot apply 5 moves, there are only 4");
+        assert_eq!(simulation.position(), 0);
+
+        simulation.seek(3).unwrap();
+        assert_eq!(simulation.tops(), "M-Z");
+    }
+
+    #[test]
+    fn test_invalid_moves() {
+        let (stacks, _) = parse(SAMPLE_INPUT);
+        let moves = [
+            "move 1 from 1 to 2",
+            "move 4 from 1 to 3",
+            "move 1 from 2 to 2",
+        ]
+        .map(|m| m.parse::<Move>().unwrap());
+        let mut simulation = Simulation::new(&CrateMover9001, stacks, &moves);
+        let err = simulation.run().unwrap_err();
+        assert_eq!(
+            err,
+            MoveError::NotEnoughCrates {
+                index: 1,
+                mv: moves[1],
+                available: 1
+            }
+        );
+        assert_eq!(
+            err.to_string(),
+            "move #2 (move 4 from 1 to 3): stack 1 only holds 1 crates"
+        );
+        assert_eq!(simulation.position(), 1);
+
+        let moves = ["move 1 from 1 to 4".parse().unwrap()];
+        let mut simulation = Simulation::new(&CrateMover9001, parse(SAMPLE_INPUT).0, &moves);
+        assert!(matches!(
+            simulation.step(),
+            Err(MoveError::NoSuchStack { stack: 3, .. })
+        ));
+
+        assert!("move 1 from 0 to 1".parse::<Move>().is_err());
+        assert!("move one from 1 to 2".parse::<Move>().is_err());
+    }
+
+    #[test]
+    fn test_render_round_trip() {
+        let header = SAMPLE_INPUT.split("\n\n").next().unwrap();
+        let stacks = parse_stacks(&mut header.lines()).unwrap();
+        assert_eq!(render(&stacks), format!("{header}\n"));
+
+        let wide = "[AB] [C] \n 1    2  \n";
+        let stacks = parse_stacks(&mut wide.lines()).unwrap();
+        assert_eq!(stacks, [["AB"], ["C"]]);
+        assert_eq!(render(&stacks), wide);
+
+        let mut stacks = vec![Vec::new(); 11];
+        stacks[0].push("A".to_owned());
+        stacks[9].extend(["Z".to_owned(), "XY".to_owned()]);
+        stacks[10].push("BIG".to_owned());
+        assert_eq!(parse_stacks(&mut render(&stacks).lines()).unwrap(), stacks);
+    }
+
+    #[test]
+    fn test_ragged_and_invalid_drawings() {
+        let ragged = "    [D]\n[N] [C]\n[Z] [M] [P]\n 1   2   3";
+        let stacks = parse_stacks(&mut ragged.lines()).unwrap();
+        assert_eq!(stacks, parse(SAMPLE_INPUT).0);
+
+        let floating = "[A]\n    [B]\n 1   2";
+        assert_eq!(
+            parse_stacks(&mut floating.lines()).unwrap_err(),
+            "Line 1: [A] is floating in mid-air"
+        );
+        assert!(parse_stacks(&mut "[A] X\n 1   2".lines()).is_err());
+        assert!(parse_stacks(&mut "[A]         [B]\n 1   2".lines()).is_err());
+        assert!(parse_stacks(&mut "[A]\n 2".lines()).is_err());
+        assert!(parse_stacks(&mut "[A]".lines()).is_err());
+    }
+}
@@ -1,17 +1,40 @@
-use core::panic;
-use std::io::{self};
+use std::{
+    collections::{HashMap, VecDeque},
+    env,
+    hash::Hash,
+    hint::black_box,
+    io::{self, Read},
+    num::NonZeroUsize,
+    sync::atomic::{AtomicUsize, Ordering},
+    thread,
+    time::Instant,
+};
 
 fn main() {
-    let mut line = String::new();
-    io::stdin().read_line(&mut line).unwrap();
+    if env::args().nth(1).as_deref() == Some("bench") {
+        let megabytes = env::args().nth(2).map_or(16, |mb| mb.parse().unwrap());
+        bench(megabytes);
+        return;
+    }
+
+    let sizes = [4, 14];
+    let input = io::stdin().lock().bytes();
 
-    let packet_start = find_start(line.as_bytes(), 4);
-    let message_start = find_start(line.as_bytes(), 14);
+    if env::args().any(|arg| arg == "--all") {
+        for marker in markers(input, &sizes) {
+            let marker = marker.unwrap();
+            println!("{} {}", sizes[marker.window], marker.end);
+        }
+        return;
+    }
 
+    let [packet_start, message_start] = first_markers(input, &sizes)
+        .unwrap()
+        .map(|x| x.expect("no marker found"));
     println!("packet: {packet_start} message: {message_start}");
 }
 
-fn find_start(input: &[u8], size: usize) -> usize {
+fn find_start(input: &[u8], size: usize) -> Option<usize> {
     let mut unique = 0;
     let mut counts = [0usize; u8::MAX as usize + 1];
     for i in 0..input.len() {
@@ -34,31 +57,286 @@ fn find_start(input: &[u8], size: usize) -> usize {
         }
 
         if unique == size {
-            return i + 1;
+            return Some(i + 1);
+        }
+    }
+    None
+}
+
+/// Same as `find_start`, but tracks the window as a bitmask over `a..=z`, using the parity of
+/// each letter's count. The window is distinct exactly when all `size` bits are set.
+///
+/// Falls back to `find_start` from the first byte outside of `a..=z` on. This is not faster than
+/// the count table, see `bench`.
+fn find_start_bitmask(input: &[u8], size: usize) -> Option<usize> {
+    if size == 0 || size > 26 {
+        return find_start(input, size);
+    }
+    let bit = |x: u8| 1u32 << (x - b'a');
+    let mut mask = 0;
+    for (i, &x) in input.iter().enumerate() {
+        if !x.is_ascii_lowercase() {
+            // Every window so far was all letters; the ones from here on may contain `x`.
+            let from = (i + 1).saturating_sub(size);
+            return find_start(&input[from..], size).map(|end| from + end);
+        }
+        mask ^= bit(x);
+        if i >= size {
+            mask ^= bit(input[i - size]);
+        }
+        if i + 1 >= size && mask.count_ones() as usize == size {
+            return Some(i + 1);
+        }
+    }
+    None
+}
+
+/// Below this many bytes, splitting the input across threads is not worth it.
+const CHUNKED_THRESHOLD: usize = 1 << 20;
+
+/// Same as `find_start`, but searches chunks of the input on all cores.
+fn find_start_chunked(input: &[u8], size: usize) -> Option<usize> {
+    let threads = thread::available_parallelism().map_or(1, NonZeroUsize::get);
+    if threads == 1 || input.len() < CHUNKED_THRESHOLD {
+        return find_start(input, size);
+    }
+    find_start_in_chunks(input, size, threads)
+}
+
+/// How many bytes a chunk searches before checking whether an earlier chunk already found a
+/// marker.
+const CANCEL_CHECK: usize = 1 << 16;
+
+/// Splits the input into `chunks` pieces that are searched on separate threads.
+///
+/// Chunks overlap by `size - 1` bytes so that no window is missed. Once a marker is found, chunks
+/// that can only find later markers stop early.
+fn find_start_in_chunks(input: &[u8], size: usize, chunks: usize) -> Option<usize> {
+    if size == 0 || input.is_empty() {
+        return find_start(input, size);
+    }
+    let chunk = input.len().div_ceil(chunks);
+    let best = AtomicUsize::new(usize::MAX);
+    thread::scope(|scope| {
+        for start in (0..input.len()).step_by(chunk) {
+            let chunk_end = usize::min(start + chunk, input.len());
+            let best = &best;
+            scope.spawn(move || {
+                for block in (start..chunk_end).step_by(CANCEL_CHECK) {
+                    // Every marker in this block ends after `block`.
+                    if best.load(Ordering::Relaxed) <= block {
+                        return;
+                    }
+                    let end = usize::min(block + CANCEL_CHECK + size - 1, input.len());
+                    if let Some(end) = find_start(&input[block..end], size) {
+                        best.fetch_min(block + end, Ordering::Relaxed);
+                        return;
+                    }
+                }
+            });
+        }
+    });
+    Some(best.into_inner()).filter(|&best| best != usize::MAX)
+}
+
+/// A xorshift generator, good enough for producing benchmark input.
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    /// `len` random letters from the first `alphabet` letters of `a..=z`.
+    fn letters(&mut self, len: usize, alphabet: u8) -> Vec<u8> {
+        (0..len)
+            .map(|_| b'a' + (self.next() % alphabet as u64) as u8)
+            .collect()
+    }
+}
+
+type Finder = fn(&[u8], usize) -> Option<usize>;
+
+/// Times each marker finder on `megabytes` of random letters.
+///
+/// The alphabet is one letter short of the window size, so there is no marker and every finder
+/// has to scan the whole input. The second run plants a marker early on, which the chunked
+/// finder should find without scanning the rest.
+///
+/// On one core with 16 MB and no marker, the count table took 18-34 ms, the bitmask 35-69 ms
+/// and the chunked finder 30-36 ms (with one core it only runs the count table). The bitmask
+/// never came out ahead, so the chunked finder searches its chunks with the count table.
+fn bench(megabytes: usize) {
+    let finders: [(&str, Finder); 4] = [
+        ("count table", find_start),
+        ("bitmask", find_start_bitmask),
+        ("chunked", find_start_chunked),
+        ("generic", |input, size| {
+            let input = input.iter().map(Ok::<_, ()>);
+            first_markers(input, &[size]).unwrap()[0]
+        }),
+    ];
+    for size in [4, 14] {
+        let mut input = Rng(0x2022_1206).letters(megabytes << 20, size as u8 - 1);
+        for marker in ["none", "early"] {
+            if marker == "early" {
+                let at = input.len() / 64;
+                input[at..at + size].copy_from_slice(&b"abcdefghijklmnopqrstuvwxyz"[..size]);
+            }
+            for (name, finder) in finders {
+                let start = Instant::now();
+                let result = black_box(finder(black_box(&input), size));
+                let elapsed = start.elapsed();
+                println!("{size:>2} {marker:<5} {name:<12} {result:>10?} {elapsed:?}");
+            }
+        }
+    }
+}
+
+/// The last `size` symbols of a stream, and how many distinct symbols they contain.
+struct DistinctWindow<T> {
+    size: usize,
+    window: VecDeque<T>,
+    counts: HashMap<T, usize>,
+}
+
+impl<T: Hash + Eq + Clone> DistinctWindow<T> {
+    fn new(size: usize) -> Self {
+        DistinctWindow {
+            size,
+            window: VecDeque::with_capacity(size + 1),
+            counts: HashMap::new(),
+        }
+    }
+
+    fn push(&mut self, symbol: T) {
+        *self.counts.entry(symbol.clone()).or_default() += 1;
+        self.window.push_back(symbol);
+        if self.window.len() > self.size {
+            let old = self.window.pop_front().unwrap();
+            let count = self.counts.get_mut(&old).unwrap();
+            *count -= 1;
+            if *count == 0 {
+                self.counts.remove(&old);
+            }
         }
     }
-    panic!("no packet start found")
+
+    fn is_distinct(&self) -> bool {
+        self.window.len() == self.size && self.counts.len() == self.size
+    }
+}
+
+/// A position where the last `sizes[window]` symbols were all different.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Marker {
+    /// Index into the window sizes the detector was created with.
+    window: usize,
+    /// The number of symbols consumed up to and including the marker.
+    end: usize,
+}
+
+/// Looks for markers of several window sizes at once, one symbol at a time.
+struct MarkerDetector<T> {
+    windows: Vec<DistinctWindow<T>>,
+    position: usize,
+}
+
+impl<T: Hash + Eq + Clone> MarkerDetector<T> {
+    fn new(sizes: &[usize]) -> Self {
+        MarkerDetector {
+            windows: sizes
+                .iter()
+                .map(|&size| DistinctWindow::new(size))
+                .collect(),
+            position: 0,
+        }
+    }
+
+    /// Consumes the next symbol and returns the markers that end at it.
+    fn push(&mut self, symbol: T) -> impl Iterator<Item = Marker> + '_ {
+        self.position += 1;
+        for window in &mut self.windows {
+            window.push(symbol.clone());
+        }
+        let end = self.position;
+        (self.windows.iter().enumerate())
+            .filter(|(_, w)| w.is_distinct())
+            .map(move |(window, _)| Marker { window, end })
+    }
+}
+
+/// Every marker in `input`, in order of their end positions. Stops at the first error.
+fn markers<T: Hash + Eq + Clone, E>(
+    input: impl IntoIterator<Item = Result<T, E>>,
+    sizes: &[usize],
+) -> impl Iterator<Item = Result<Marker, E>> {
+    let mut detector = MarkerDetector::new(sizes);
+    let mut failed = false;
+    (input.into_iter())
+        .map_while(move |symbol| match symbol {
+            _ if failed => None,
+            Ok(symbol) => Some(detector.push(symbol).map(Ok).collect::<Vec<_>>()),
+            Err(e) => {
+                failed = true;
+                Some(vec![Err(e)])
+            }
+        })
+        .flatten()
+}
+
+/// The end of the first marker for each of `sizes`, reading only as far as necessary.
+fn first_markers<T: Hash + Eq + Clone, E, const N: usize>(
+    input: impl IntoIterator<Item = Result<T, E>>,
+    sizes: &[usize; N],
+) -> Result<[Option<usize>; N], E> {
+    let mut first = [None; N];
+    for marker in markers(input, sizes) {
+        let marker = marker?;
+        first[marker.window].get_or_insert(marker.end);
+        if first.iter().all(Option::is_some) {
+            break;
+        }
+    }
+    Ok(first)
 }
 
 #[cfg(test)]
 mod test {
-    use super::find_start;
+    use super::*;
+
+    static SAMPLES: [(&str, usize, usize); 5] = [
+        ("mjqjpqmgbljsphdztnvjfqwrcgsmlb", 7, 19),
+        ("bvwbjplbgvbhsrlpgdmjqwftvncz", 5, 23),
+        ("nppdvjthqldpwncqszvftbrmjlhg", 6, 23),
+        ("nznrnfrfntjfmvfwmzdfjlvtqnbhcprsg", 10, 29),
+        ("zcfzfwzzqfrljwzlrfnpqdbhtmscgvjw", 11, 26),
+    ];
 
     #[test]
     fn sample_part1() {
         assert_eq!(
             find_start("mjqjpqmgbljsphdztnvjfqwrcgsmlb".as_bytes(), 4),
-            7
+            Some(7)
+        );
+        assert_eq!(
+            find_start("bvwbjplbgvbhsrlpgdmjqwftvncz".as_bytes(), 4),
+            Some(5)
+        );
+        assert_eq!(
+            find_start("nppdvjthqldpwncqszvftbrmjlhg".as_bytes(), 4),
+            Some(6)
         );
-        assert_eq!(find_start("bvwbjplbgvbhsrlpgdmjqwftvncz".as_bytes(), 4), 5);
-        assert_eq!(find_start("nppdvjthqldpwncqszvftbrmjlhg".as_bytes(), 4), 6);
         assert_eq!(
             find_start("nznrnfrfntjfmvfwmzdfjlvtqnbhcprsg".as_bytes(), 4),
-            10
+            Some(10)
         );
         assert_eq!(
             find_start("zcfzfwzzqfrljwzlrfnpqdbhtmscgvjw".as_bytes(), 4),
-            11
+            Some(11)
         );
     }
 
@@ -66,28 +344,110 @@ mod test {
     fn sample_part2() {
         assert_eq!(
             find_start("mjqjpqmgbljsphdztnvjfqwrcgsmlb".as_bytes(), 14),
-            19
+            Some(19)
         );
         assert_eq!(
             find_start("bvwbjplbgvbhsrlpgdmjqwftvncz".as_bytes(), 14),
-            23
+            Some(23)
         );
         assert_eq!(
             find_start("nppdvjthqldpwncqszvftbrmjlhg".as_bytes(), 14),
-            23
+            Some(23)
         );
         assert_eq!(
             find_start("nznrnfrfntjfmvfwmzdfjlvtqnbhcprsg".as_bytes(), 14),
-            29
+            Some(29)
         );
         assert_eq!(
             find_start("zcfzfwzzqfrljwzlrfnpqdbhtmscgvjw".as_bytes(), 14),
-            26
+            Some(26)
         );
     }
 
     #[test]
     fn other() {
-        assert_eq!(find_start("abcd".as_bytes(), 4), 4);
+        assert_eq!(find_start("abcd".as_bytes(), 4), Some(4));
+        assert_eq!(find_start("abca".as_bytes(), 4), None);
+    }
+
+    fn symbols<T>(input: impl IntoIterator<Item = T>) -> impl Iterator<Item = Result<T, ()>> {
+        input.into_iter().map(Ok)
+    }
+
+    #[test]
+    fn test_first_markers() {
+        for (input, packet, message) in SAMPLES {
+            let expected = Ok([Some(packet), Some(message)]);
+            assert_eq!(first_markers(symbols(input.chars()), &[4, 14]), expected);
+            assert_eq!(
+                first_markers(input.as_bytes().bytes(), &[4, 14]).ok(),
+                expected.ok()
+            );
+        }
+        assert_eq!(first_markers(symbols([1, 2, 1, 2, 1]), &[3]), Ok([None]));
+        let requests = ["GET", "PUT", "GET", "POST"];
+        assert_eq!(
+            first_markers(symbols(requests), &[2, 3]),
+            Ok([Some(2), Some(4)])
+        );
+    }
+
+    #[test]
+    fn test_markers() {
+        let all = markers(symbols("abcabbc".chars()), &[2, 3])
+            .map(Result::unwrap)
+            .collect::<Vec<_>>();
+        let ends = |window| {
+            all.iter()
+                .filter(|m| m.window == window)
+                .map(|m| m.end)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(ends(0), [2, 3, 4, 5, 7]);
+        assert_eq!(ends(1), [3, 4, 5]);
+
+        let input = [Ok('a'), Ok('b'), Err("broken"), Ok('c')];
+        let all = markers(input, &[2]).collect::<Vec<_>>();
+        assert_eq!(all, [Ok(Marker { window: 0, end: 2 }), Err("broken")]);
+    }
+
+    #[test]
+    fn test_fast_paths_match_find_start() {
+        for (input, _, _) in SAMPLES {
+            for input in [
+                input.to_owned(),
+                format!("{input}\n"),
+                format!("{input}ABC"),
+            ] {
+                let input = input.as_bytes();
+                for size in (1..=16).chain([26, 27]) {
+                    let expected = find_start(input, size);
+                    assert_eq!(
+                        find_start_bitmask(input, size),
+                        expected,
+                        "{input:?} {size}"
+                    );
+                    for chunks in [1, 3, 7] {
+                        let found = find_start_in_chunks(input, size, chunks);
+                        assert_eq!(found, expected, "{input:?} {size} {chunks}");
+                    }
+                }
+            }
+        }
+        assert_eq!(find_start_bitmask(b"ABCD", 4), Some(4));
+        assert_eq!(find_start_bitmask(b"ab\nab", 3), Some(3));
+        assert_eq!(find_start_bitmask(b"aa\naa", 3), None);
+        assert_eq!(find_start_in_chunks(b"aaA\nb", 3, 3), Some(4));
+
+        // Thirteen letters over and over never hold a marker of fourteen.
+        let mut input = b"abcdefghijklm".repeat(CHUNKED_THRESHOLD / 13 + 1);
+        assert_eq!(find_start_chunked(&input, 14), None);
+        input[4 * CANCEL_CHECK..][..14].copy_from_slice(b"abcdefghijklmn");
+        assert_eq!(find_start_chunked(&input, 14), find_start(&input, 14));
+        input[100..114].copy_from_slice(b"abcdefghijklmn");
+        for chunks in [1, 4, 16] {
+            let found = find_start_in_chunks(&input, 14, chunks);
+            assert_eq!(found, find_start(&input, 14), "{chunks}");
+        }
     }
 }
@@ -1,59 +1,297 @@
 use std::{
-    collections::HashMap,
+    cell::OnceCell,
+    collections::{BTreeMap, BTreeSet, HashMap},
+    env,
+    fmt::Display,
     io::{self, BufRead},
+    mem,
     ops::Deref,
+    process,
+    str::FromStr,
 };
 
-#[derive(Default, Debug)]
-struct Dir {
-    dirs: HashMap<String, Dir>,
-    files: HashMap<String, usize>,
+type NodeId = usize;
+
+const ROOT: NodeId = 0;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Node {
+    Dir(BTreeMap<String, NodeId>),
+    File(usize),
+}
+
+#[derive(Debug)]
+struct Entry {
+    name: String,
+    parent: Option<NodeId>,
+    node: Node,
+}
+
+/// The file system as seen through the terminal transcript.
+///
+/// Entries are only ever added, and always after their parent, so a child's id is always
+/// greater than its parent's.
+#[derive(Debug)]
+struct Vfs {
+    entries: Vec<Entry>,
+    /// The recursive size of every entry, computed on first use.
+    sizes: OnceCell<Vec<usize>>,
 }
 
-impl Dir {
-    fn size(&self) -> usize {
-        let files: usize = self.files.values().sum();
-        let dirs: usize = self.dirs.values().map(|x| x.size()).sum();
-        files + dirs
+impl Vfs {
+    fn new() -> Self {
+        Vfs {
+            entries: vec![Entry {
+                name: String::new(),
+                parent: None,
+                node: Node::Dir(BTreeMap::new()),
+            }],
+            sizes: OnceCell::new(),
+        }
     }
 
-    fn sizes(&self, name: String) -> Vec<(String, usize)> {
-        let mut sizes = self
-            .dirs
-            .iter()
-            .flat_map(|(n, d)| d.sizes(n.to_owned()))
-            .collect::<Vec<_>>();
-        sizes.push((name, self.size()));
-        sizes
+    fn is_dir(&self, id: NodeId) -> bool {
+        matches!(self.entries[id].node, Node::Dir(_))
+    }
+
+    fn parent(&self, id: NodeId) -> Option<NodeId> {
+        self.entries[id].parent
+    }
+
+    fn children(&self, id: NodeId) -> impl Iterator<Item = NodeId> + '_ {
+        let children = match &self.entries[id].node {
+            Node::Dir(children) => Some(children.values().copied()),
+            Node::File(_) => None,
+        };
+        children.into_iter().flatten()
+    }
+
+    fn child(&self, id: NodeId, name: &str) -> Option<NodeId> {
+        match &self.entries[id].node {
+            Node::Dir(children) => children.get(name).copied(),
+            Node::File(_) => None,
+        }
+    }
+
+    /// Adds `node` to the directory `parent`, or returns the existing entry with that name.
+    fn insert(&mut self, parent: NodeId, name: &str, node: Node) -> Result<NodeId, NodeId> {
+        if let Some(existing) = self.child(parent, name) {
+            return Err(existing);
+        }
+        let id = self.entries.len();
+        let Node::Dir(children) = &mut self.entries[parent].node else {
+            panic!("{} is not a directory", self.path(parent));
+        };
+        children.insert(name.to_owned(), id);
+        self.entries.push(Entry {
+            name: name.to_owned(),
+            parent: Some(parent),
+            node,
+        });
+        self.sizes.take();
+        Ok(id)
+    }
+
+    fn path(&self, id: NodeId) -> String {
+        match self.parent(id) {
+            None => "/".to_owned(),
+            Some(ROOT) => format!("/{}", self.entries[id].name),
+            Some(parent) => format!("{}/{}", self.path(parent), self.entries[id].name),
+        }
+    }
+
+    /// Resolves an absolute path such as `/a/e`.
+    fn lookup(&self, path: &str) -> Option<NodeId> {
+        let path = path.strip_prefix('/')?;
+        path.split('/')
+            .filter(|name| !name.is_empty())
+            .try_fold(ROOT, |id, name| self.child(id, name))
+    }
+
+    fn size(&self, id: NodeId) -> usize {
+        let sizes = self.sizes.get_or_init(|| {
+            let mut sizes = self
+                .entries
+                .iter()
+                .map(|e| match e.node {
+                    Node::File(size) => size,
+                    Node::Dir(_) => 0,
+                })
+                .collect::<Vec<_>>();
+            for id in (1..self.entries.len()).rev() {
+                sizes[self.parent(id).unwrap()] += sizes[id];
+            }
+            sizes
+        });
+        sizes[id]
+    }
+
+    /// The entries below `root` in depth-first order, directories before their contents.
+    fn walk(&self, root: NodeId) -> Vec<(NodeId, usize)> {
+        let mut result = Vec::new();
+        let mut stack = vec![(root, 0)];
+        while let Some((id, depth)) = stack.pop() {
+            result.push((id, depth));
+            let children = self.children(id).collect::<Vec<_>>();
+            stack.extend(children.into_iter().rev().map(|child| (child, depth + 1)));
+        }
+        result
+    }
+
+    /// Every directory with its recursive size, like `du`.
+    fn du(&self) -> Vec<(String, usize)> {
+        self.walk(ROOT)
+            .into_iter()
+            .map(|(id, _)| id)
+            .filter(|&id| self.is_dir(id))
+            .map(|id| (self.path(id), self.size(id)))
+            .collect()
+    }
+
+    fn find(&self, query: &Query) -> Vec<NodeId> {
+        self.walk(ROOT)
+            .into_iter()
+            .map(|(id, _)| id)
+            .filter(|&id| query.matches(self, id))
+            .collect()
+    }
+
+    /// Renders the hierarchy below `root` like the puzzle statement does, with sizes for
+    /// directories too.
+    fn tree(&self, root: NodeId) -> String {
+        let mut result = String::new();
+        for (id, depth) in self.walk(root) {
+            let name = match depth {
+                0 => self.path(id),
+                _ => self.entries[id].name.clone(),
+            };
+            let kind = if self.is_dir(id) { "dir" } else { "file" };
+            result += &format!(
+                "{}- {name} ({kind}, size={})\n",
+                "  ".repeat(depth),
+                self.size(id)
+            );
+        }
+        result
+    }
+}
+
+/// Matches `name` against a pattern where `*` is any run of characters and `?` any single one.
+fn glob_match(pattern: &str, name: &str) -> bool {
+    let pattern = pattern.chars().collect::<Vec<_>>();
+    let name = name.chars().collect::<Vec<_>>();
+    let (mut p, mut n) = (0, 0);
+    // Where to resume after the last `*`: its position in the pattern, and how much of the name
+    // it has consumed so far.
+    let mut backtrack = None;
+    while n < name.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p, n));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == name[n] => {
+                p += 1;
+                n += 1;
+            }
+            _ => match backtrack {
+                Some((star, consumed)) => {
+                    backtrack = Some((star, consumed + 1));
+                    p = star + 1;
+                    n = consumed + 1;
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    Dir,
+    File,
+}
+
+/// Predicates for `Vfs::find`, all of which have to hold.
+#[derive(Debug, Default)]
+struct Query {
+    name: Option<String>,
+    kind: Option<Kind>,
+    min_size: Option<usize>,
+    max_size: Option<usize>,
+}
+
+impl Query {
+    /// Parses `find`-style arguments: `-name <glob>`, `-type d|f` and `-size +<min>|-<max>`.
+    fn parse(args: impl IntoIterator<Item = String>) -> Result<Query, String> {
+        let mut query = Query::default();
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            let value = args
+                .next()
+                .ok_or_else(|| format!("Missing value for {arg}"))?;
+            let size = |s: &str| s.parse().map_err(|_| format!("Not a size: {value}"));
+            match (arg.as_str(), value.as_str()) {
+                ("-name", _) => query.name = Some(value.clone()),
+                ("-type", "d") => query.kind = Some(Kind::Dir),
+                ("-type", "f") => query.kind = Some(Kind::File),
+                ("-size", v) if v.starts_with('+') => query.min_size = Some(size(&v[1..])?),
+                ("-size", v) if v.starts_with('-') => query.max_size = Some(size(&v[1..])?),
+                _ => return Err(format!("Unexpected argument: {arg} {value}")),
+            }
+        }
+        Ok(query)
+    }
+
+    fn matches(&self, vfs: &Vfs, id: NodeId) -> bool {
+        let kind = if vfs.is_dir(id) {
+            Kind::Dir
+        } else {
+            Kind::File
+        };
+        let size = vfs.size(id);
+        self.name
+            .as_ref()
+            .is_none_or(|glob| glob_match(glob, &vfs.entries[id].name))
+            && self.kind.is_none_or(|k| k == kind)
+            && self.min_size.is_none_or(|min| size >= min)
+            && self.max_size.is_none_or(|max| size <= max)
     }
 }
 
 enum Line {
-    Cd(Cd),
-    Ls,
+    Cd(String),
+    Ls(Option<String>),
     DirEnt(DirEnt),
 }
 
-impl<T: Deref<Target = str>> From<T> for Line {
-    fn from(s: T) -> Self {
-        let s = &*s;
-        if let Some(dir) = s.strip_prefix("$ cd ") {
-            Line::Cd(match dir {
-                "/" => Cd::Root,
-                ".." => Cd::Up,
-                path => Cd::Into(path.to_owned()),
-            })
-        } else if s == "$ ls" {
-            Line::Ls
-        } else if let Some(name) = s.strip_prefix("dir ") {
-            Line::DirEnt(DirEnt::Dir(name.to_owned()))
+impl FromStr for Line {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some(command) = s.strip_prefix("$ ") {
+            let mut words = command.split_whitespace();
+            match (words.next(), words.next(), words.next()) {
+                (Some("cd"), Some(path), None) => Ok(Line::Cd(path.to_owned())),
+                (Some("ls"), path, None) => Ok(Line::Ls(path.map(str::to_owned))),
+                _ => Err(format!("unsupported command: {command}")),
+            }
         } else {
-            let (size, name) = s.split_once(' ').unwrap();
+            let (size, name) = s
+                .split_once(' ')
+                .ok_or_else(|| format!("unexpected output: {s}"))?;
+            if name.is_empty() || name.contains('/') {
+                return Err(format!("invalid name: {name:?}"));
+            }
+            let name = name.to_owned();
+            if size == "dir" {
+                return Ok(Line::DirEnt(DirEnt::Dir(name)));
+            }
             let size = size
                 .parse::<usize>()
-                .expect(format!("not a number: {s}").as_str());
-            let name = name.to_owned();
-            Line::DirEnt(DirEnt::File { size, name })
+                .map_err(|_| format!("not a number: {size}"))?;
+            Ok(Line::DirEnt(DirEnt::File { size, name }))
         }
     }
 }
@@ -63,77 +301,400 @@ enum DirEnt {
     File { size: usize, name: String },
 }
 
-enum Cd {
-    Root,
-    Up,
-    Into(String),
+/// Something in the transcript that does not add up, with its 1-based line number.
+#[derive(Debug, PartialEq, Eq)]
+struct Issue {
+    line: usize,
+    message: String,
 }
 
-fn main() {
-    let stdin = io::stdin();
-    let lines = stdin.lock().lines().map(|x| x.unwrap());
-    let root = parse(lines);
-    println!("part1: {}", part1(&root));
-    println!("part2: {}", part2(&root));
-}
-
-enum ParseResult {
-    Up,
-    Root,
-    Eof,
-}
-
-fn parse_into(
-    dir: &mut Dir,
-    lines: &mut impl Iterator<Item = impl Deref<Target = str>>,
-) -> ParseResult {
-    while let Some(line) = lines.next() {
-        match Line::from(line) {
-            Line::Cd(cd) => match cd {
-                Cd::Root => return ParseResult::Root,
-                Cd::Up => return ParseResult::Up,
-                Cd::Into(path) => {
-                    let mut inner = dir.dirs.entry(path).or_default();
-                    match parse_into(&mut inner, lines) {
-                        ParseResult::Root => return ParseResult::Root,
-                        ParseResult::Eof => return ParseResult::Eof,
-                        ParseResult::Up => (),
+impl Display for Issue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+/// The output of an `ls` that is still being read.
+struct Listing {
+    dir: NodeId,
+    line: usize,
+    names: BTreeSet<String>,
+}
+
+/// Replays a transcript into a `Vfs`, noting every inconsistency along the way.
+struct Interpreter {
+    vfs: Vfs,
+    cwd: NodeId,
+    issues: Vec<Issue>,
+    /// The line every entry was first seen on, by id.
+    seen_on: Vec<usize>,
+    /// What each listed directory contained, and the line of the `ls` that showed it.
+    listed: HashMap<NodeId, (usize, BTreeSet<String>)>,
+    listing: Option<Listing>,
+}
+
+impl Interpreter {
+    fn new() -> Self {
+        Interpreter {
+            vfs: Vfs::new(),
+            cwd: ROOT,
+            issues: Vec::new(),
+            seen_on: vec![0],
+            listed: HashMap::new(),
+            listing: None,
+        }
+    }
+
+    fn issue(&mut self, line: usize, message: String) {
+        self.issues.push(Issue { line, message });
+    }
+
+    fn insert(
+        &mut self,
+        line: usize,
+        dir: NodeId,
+        name: &str,
+        node: Node,
+    ) -> Result<NodeId, NodeId> {
+        let id = self.vfs.insert(dir, name, node)?;
+        self.seen_on.push(line);
+        Ok(id)
+    }
+
+    /// Resolves a `cd` or `ls` argument such as `../b/c` or `/a`. Directories that were never
+    /// listed are reported, but created so that the rest of the transcript still makes sense.
+    fn resolve(&mut self, line: usize, path: &str) -> Option<NodeId> {
+        let mut dir = if path.starts_with('/') {
+            ROOT
+        } else {
+            self.cwd
+        };
+        for name in path.split('/') {
+            match name {
+                "" | "." => (),
+                ".." => dir = self.vfs.parent(dir).unwrap_or(ROOT),
+                name => match self.vfs.child(dir, name) {
+                    Some(child) if self.vfs.is_dir(child) => dir = child,
+                    Some(child) => {
+                        let path = self.vfs.path(child);
+                        self.issue(line, format!("{path} is a file, not a directory"));
+                        return None;
+                    }
+                    None => {
+                        let new = self.insert(line, dir, name, Node::Dir(BTreeMap::new()));
+                        dir = new.unwrap();
+                        let path = self.vfs.path(dir);
+                        self.issue(line, format!("{path} was never listed"));
                     }
+                },
+            }
+        }
+        Some(dir)
+    }
+
+    fn finish_listing(&mut self) {
+        let Some(Listing { dir, line, names }) = self.listing.take() else {
+            return;
+        };
+        let Some((first, listed)) = self.listed.get(&dir) else {
+            self.listed.insert(dir, (line, names));
+            return;
+        };
+        let path = |name: &String| self.vfs.path(self.vfs.child(dir, name).unwrap());
+        let mut issues = Vec::new();
+        for missing in listed.difference(&names) {
+            issues.push(format!("{} from line {first} is missing", path(missing)));
+        }
+        for new in names.difference(listed) {
+            issues.push(format!("{} was not there on line {first}", path(new)));
+        }
+        for message in issues {
+            self.issue(line, message);
+        }
+    }
+
+    fn list(&mut self, line: usize, entry: DirEnt) {
+        let Some(listing) = &mut self.listing else {
+            self.issue(line, "output without a preceding ls".to_owned());
+            return;
+        };
+        let dir = listing.dir;
+        let (name, node) = match entry {
+            DirEnt::Dir(name) => (name, Node::Dir(BTreeMap::new())),
+            DirEnt::File { size, name } => (name, Node::File(size)),
+        };
+        if !listing.names.insert(name.clone()) {
+            let path = self.vfs.path(dir);
+            self.issue(line, format!("{path}/{name} is listed twice"));
+        }
+        let Err(existing) = self.insert(line, dir, &name, node.clone()) else {
+            return;
+        };
+        let first = self.seen_on[existing];
+        let path = self.vfs.path(existing);
+        match (&self.vfs.entries[existing].node, &node) {
+            (Node::File(before), Node::File(now)) if before != now => self.issue(
+                line,
+                format!("{path} has size {now}, but {before} on line {first}"),
+            ),
+            (Node::File(_), Node::Dir(_)) => self.issue(
+                line,
+                format!("{path} is a directory, but a file on line {first}"),
+            ),
+            (Node::Dir(_), Node::File(_)) => self.issue(
+                line,
+                format!("{path} is a file, but a directory on line {first}"),
+            ),
+            _ => (),
+        }
+    }
+
+    fn interpret(&mut self, line: usize, text: &str) {
+        let parsed = match text.parse::<Line>() {
+            Ok(parsed) => parsed,
+            Err(message) => return self.issue(line, message),
+        };
+        if let Line::Cd(_) | Line::Ls(_) = parsed {
+            self.finish_listing();
+        }
+        match parsed {
+            Line::Cd(path) => {
+                if let Some(dir) = self.resolve(line, &path) {
+                    self.cwd = dir;
                 }
-            },
-            Line::Ls => (),
-            Line::DirEnt(d) => match d {
-                DirEnt::Dir(_) => (),
-                DirEnt::File { size, name } => {
-                    dir.files.insert(name, size);
+            }
+            Line::Ls(path) => {
+                let dir = match path {
+                    Some(path) => self.resolve(line, &path),
+                    None => Some(self.cwd),
+                };
+                self.listing = dir.map(|dir| Listing {
+                    dir,
+                    line,
+                    names: BTreeSet::new(),
+                });
+            }
+            Line::DirEnt(entry) => self.list(line, entry),
+        }
+    }
+}
+
+/// Replays the transcript, returning the file system it describes and everything that seemed
+/// off about it.
+fn interpret(lines: impl Iterator<Item = impl Deref<Target = str>>) -> (Vfs, Vec<Issue>) {
+    let mut interpreter = Interpreter::new();
+    for (i, line) in lines.enumerate() {
+        interpreter.interpret(i + 1, &line);
+    }
+    interpreter.finish_listing();
+    interpreter.issues.sort_by_key(|issue| issue.line);
+    (interpreter.vfs, interpreter.issues)
+}
+
+const DISK_SIZE: usize = 70000000;
+const UPDATE_SIZE: usize = 30000000;
+
+/// Directories to delete, none of them inside another, and how much that frees.
+#[derive(Debug, PartialEq, Eq)]
+struct Plan {
+    delete: Vec<NodeId>,
+    freed: usize,
+}
+
+/// How a reachable amount is freed: by deleting nothing, one directory, or both of two earlier
+/// choices.
+enum Choice {
+    Keep,
+    Delete(NodeId),
+    Both(usize, usize),
+}
+
+/// The most choices `plan_cleanup` keeps track of before giving up.
+const MAX_CHOICES: usize = 1 << 20;
+
+/// Finds the directories to delete so that a disk of `disk` bytes has `required` bytes free,
+/// deleting as little as possible. Protected directories, their contents and the directories
+/// containing them are never deleted.
+///
+/// This is a knapsack over the tree: every directory collects the amounts its subtree can free,
+/// from its subdirectories combined or from deleting it as a whole, and each amount remembers
+/// the choice that reached it first. Only amounts that actually occur are stored.
+fn plan_cleanup(
+    vfs: &Vfs,
+    disk: usize,
+    required: usize,
+    protected: &[NodeId],
+) -> Result<Plan, String> {
+    let needed = (vfs.size(ROOT) + required).saturating_sub(disk);
+
+    let mut inside = vec![false; vfs.entries.len()];
+    let mut contains = vec![false; vfs.entries.len()];
+    for &id in protected {
+        inside[id] = true;
+        contains[id] = true;
+    }
+    for id in 1..vfs.entries.len() {
+        inside[id] |= inside[vfs.parent(id).unwrap()];
+    }
+    for id in (1..vfs.entries.len()).rev() {
+        contains[vfs.parent(id).unwrap()] |= contains[id];
+    }
+    let deletable = |id: NodeId| vfs.is_dir(id) && !inside[id] && !contains[id];
+
+    // Deleting the smallest directory that is big enough is always possible, so nothing bigger
+    // needs to be tracked. Without one, everything that can be deleted is the limit.
+    let dirs = (0..vfs.entries.len()).filter(|&id| deletable(id));
+    let limit = dirs
+        .clone()
+        .map(|id| vfs.size(id))
+        .filter(|&size| size >= needed)
+        .min()
+        .unwrap_or_else(|| {
+            dirs.filter(|&id| vfs.parent(id).is_none_or(|p| !deletable(p)))
+                .map(|id| vfs.size(id))
+                .sum()
+        });
+    if limit < needed {
+        return Err(format!("Cannot free {required} bytes"));
+    }
+
+    let mut choices = vec![Choice::Keep];
+    // The amounts each directory's subtree can free, and the choice for each; children have
+    // greater ids than their parents, so they are done first.
+    let mut freeable = (0..vfs.entries.len())
+        .map(|_| BTreeMap::new())
+        .collect::<Vec<BTreeMap<usize, usize>>>();
+    for id in (0..vfs.entries.len()).rev().filter(|&id| vfs.is_dir(id)) {
+        let mut amounts = BTreeMap::from([(0, 0)]);
+        for child in vfs.children(id) {
+            let below = mem::take(&mut freeable[child]);
+            if below.len() <= 1 {
+                continue;
+            }
+            let mut combined = BTreeMap::new();
+            for (&a, &first) in &amounts {
+                for (&b, &second) in below.range(..=limit - a) {
+                    combined.entry(a + b).or_insert_with(|| {
+                        choices.push(Choice::Both(first, second));
+                        choices.len() - 1
+                    });
                 }
-            },
+            }
+            amounts = combined;
+            if choices.len() > MAX_CHOICES {
+                return Err(format!(
+                    "Too many ways to combine directories, gave up after {MAX_CHOICES}"
+                ));
+            }
+        }
+        if deletable(id) && vfs.size(id) <= limit {
+            amounts.entry(vfs.size(id)).or_insert_with(|| {
+                choices.push(Choice::Delete(id));
+                choices.len() - 1
+            });
         }
+        freeable[id] = amounts;
     }
-    ParseResult::Eof
+
+    let (&freed, &choice) = freeable[ROOT]
+        .range(needed..)
+        .next()
+        .ok_or_else(|| format!("Cannot free {required} bytes"))?;
+    let mut delete = Vec::new();
+    let mut pending = vec![choice];
+    while let Some(choice) = pending.pop() {
+        match choices[choice] {
+            Choice::Keep => {}
+            Choice::Delete(id) => delete.push(id),
+            Choice::Both(first, second) => pending.extend([second, first]),
+        }
+    }
+    Ok(Plan { delete, freed })
 }
 
-fn parse(lines: impl Iterator<Item = impl Deref<Target = str>>) -> Dir {
-    let mut root = Dir::default();
-    let mut lines = lines;
-    loop {
-        if let ParseResult::Eof = parse_into(&mut root, &mut lines) {
-            return root;
+fn main() {
+    let stdin = io::stdin();
+    let lines = stdin.lock().lines().map(|x| x.unwrap());
+    let mut args = env::args().skip(1);
+    let command = args.next();
+    if command.as_deref() == Some("check") {
+        let (_, issues) = interpret(lines);
+        for issue in &issues {
+            println!("{issue}");
+        }
+        process::exit(if issues.is_empty() { 0 } else { 1 });
+    }
+
+    let vfs = parse(lines);
+    match command.as_deref() {
+        None => {
+            println!("part1: {}", part1(&vfs));
+            println!("part2: {}", part2(&vfs));
+        }
+        Some("tree") => {
+            let path = args.next().unwrap_or_else(|| "/".to_owned());
+            let root = vfs
+                .lookup(&path)
+                .unwrap_or_else(|| panic!("No such path: {path}"));
+            print!("{}", vfs.tree(root));
+        }
+        Some("du") => {
+            for (path, size) in vfs.du() {
+                println!("{size}\t{path}");
+            }
         }
+        Some("find") => {
+            let query = Query::parse(args).unwrap_or_else(|e| panic!("{e}"));
+            for id in vfs.find(&query) {
+                println!("{}\t{}", vfs.size(id), vfs.path(id));
+            }
+        }
+        Some("plan") => {
+            let (mut disk, mut required, mut protected) = (DISK_SIZE, UPDATE_SIZE, Vec::new());
+            while let Some(arg) = args.next() {
+                let value = args
+                    .next()
+                    .unwrap_or_else(|| panic!("Missing value for {arg}"));
+                match arg.as_str() {
+                    "--disk" => disk = value.parse().unwrap(),
+                    "--required" => required = value.parse().unwrap(),
+                    "--protect" => protected.push(
+                        vfs.lookup(&value)
+                            .unwrap_or_else(|| panic!("No such path: {value}")),
+                    ),
+                    _ => panic!("Unexpected argument: {arg}"),
+                }
+            }
+            let plan =
+                plan_cleanup(&vfs, disk, required, &protected).unwrap_or_else(|e| panic!("{e}"));
+            for id in plan.delete {
+                println!("{}\t{}", vfs.size(id), vfs.path(id));
+            }
+            println!("freed: {}", plan.freed);
+        }
+        Some(other) => panic!("Unknown command: {other}"),
+    }
+}
+
+fn parse(lines: impl Iterator<Item = impl Deref<Target = str>>) -> Vfs {
+    let (vfs, issues) = interpret(lines);
+    for issue in issues {
+        eprintln!("{issue}");
     }
+    vfs
 }
 
-fn part1(root: &Dir) -> usize {
-    root.sizes("".to_owned())
+fn part1(vfs: &Vfs) -> usize {
+    vfs.du()
         .into_iter()
         .map(|x| x.1)
         .filter(|&x| x <= 100000)
         .sum::<usize>()
 }
 
-fn part2(root: &Dir) -> usize {
-    let needed = root.size() - (70000000 - 30000000);
-    root.sizes("/".to_owned())
+fn part2(vfs: &Vfs) -> usize {
+    let needed = vfs.size(ROOT) - (DISK_SIZE - UPDATE_SIZE);
+    vfs.du()
         .into_iter()
         .filter(|x| x.1 >= needed)
         .min_by_key(|x| x.1)
@@ -185,4 +746,340 @@ mod test {
         let root = parse(sample_lines());
         assert_eq!(part2(&root), 24933642);
     }
+
+    #[test]
+    fn test_paths_and_sizes() {
+        let vfs = parse(sample_lines());
+        let e = vfs.lookup("/a/e").unwrap();
+        assert_eq!(vfs.path(e), "/a/e");
+        assert_eq!(vfs.size(e), 584);
+        assert_eq!(vfs.lookup("/"), Some(ROOT));
+        assert_eq!(vfs.lookup("/a/x"), None);
+        assert_eq!(
+            vfs.du(),
+            [
+                ("/".to_owned(), 48381165),
+                ("/a".to_owned(), 94853),
+                ("/a/e".to_owned(), 584),
+                ("/d".to_owned(), 24933642),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_find() {
+        let vfs = parse(sample_lines());
+        let find = |args: &[&str]| {
+            let query = Query::parse(args.iter().map(|&a| a.to_owned())).unwrap();
+            vfs.find(&query)
+                .into_iter()
+                .map(|id| vfs.path(id))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(find(&["-name", "d*"]), ["/d", "/d/d.ext", "/d/d.log"]);
+        assert_eq!(
+            find(&["-name", "?.*", "-size", "+8000000"]),
+            ["/b.txt", "/c.dat", "/d/d.log"]
+        );
+        assert_eq!(find(&["-type", "d", "-size", "-100000"]), ["/a", "/a/e"]);
+        assert!(Query::parse(["-type".to_owned(), "x".to_owned()]).is_err());
+    }
+
+    #[test]
+    fn test_glob_match() {
+        assert!(glob_match("*.txt", "b.txt"));
+        assert!(glob_match("*", ""));
+        assert!(glob_match("a*b*c", "aXbYbZc"));
+        assert!(!glob_match("a*b", "aXbY"));
+        assert!(!glob_match("?", ""));
+    }
+
+    #[test]
+    fn test_tree() {
+        let vfs = parse(sample_lines());
+        assert_eq!(
+            vfs.tree(ROOT),
+            "\
+- / (dir, size=48381165)
+  - a (dir, size=94853)
+    - e (dir, size=584)
+      - i (file, size=584)
+    - f (file, size=29116)
+    - g (file, size=2557)
+    - h.lst (file, size=62596)
+  - b.txt (file, size=14848514)
+  - c.dat (file, size=8504156)
+  - d (dir, size=24933642)
+    - d.ext (file, size=5626152)
+    - d.log (file, size=8033020)
+    - j (file, size=4060174)
+    - k (file, size=7214296)
+"
+        );
+        assert_eq!(
+            vfs.tree(vfs.lookup("/a/e").unwrap()),
+            "- /a/e (dir, size=584)\n  - i (file, size=584)\n"
+        );
+    }
+
+    fn issues(transcript: &str) -> Vec<String> {
+        let (_, issues) = interpret(transcript.lines().map(|x| x.trim()));
+        issues.into_iter().map(|i| i.to_string()).collect()
+    }
+
+    #[test]
+    fn test_sample_is_consistent() {
+        assert_eq!(issues(SAMPLE_INPUT), Vec::<String>::new());
+    }
+
+    #[test]
+    fn test_paths() {
+        let (vfs, issues) = interpret(
+            "\
+            $ ls
+            dir a
+            $ ls a
+            dir b
+            $ cd a/b/..
+            $ ls ./b
+            10 x
+            $ cd /a/b
+            $ ls ../../a/b/.
+            10 x"
+                .lines()
+                .map(|x| x.trim()),
+        );
+        assert_eq!(issues, []);
+        let b = vfs.lookup("/a/b").unwrap();
+        assert_eq!(vfs.size(b), 10);
+        assert_eq!(vfs.size(ROOT), 10);
+    }
+
+    #[test]
+    fn test_inconsistencies() {
+        assert_eq!(
+            issues(
+                "\
+                $ cd /
+                $ ls
+                dir a
+                10 f
+                $ cd b
+                $ cd /
+                $ ls
+                dir a
+                20 f
+                30 g
+                $ cd f
+                12 h
+                $ rm -rf /
+                garbage
+                $ ls a
+                1 x
+                1 x"
+            ),
+            [
+                "line 5: /b was never listed",
+                "line 7: /g was not there on line 2",
+                "line 9: /f has size 20, but 10 on line 4",
+                "line 11: /f is a file, not a directory",
+                "line 12: output without a preceding ls",
+                "line 13: unsupported command: rm -rf /",
+                "line 14: unexpected output: garbage",
+                "line 17: /a/x is listed twice",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_plan_cleanup() {
+        let vfs = parse(sample_lines());
+        let path = |path| vfs.lookup(path).unwrap();
+        let paths = |plan: Plan| {
+            let paths = plan.delete.into_iter().map(|id| vfs.path(id));
+            (paths.collect::<Vec<_>>(), plan.freed)
+        };
+
+        let plan = plan_cleanup(&vfs, DISK_SIZE, UPDATE_SIZE, &[]).unwrap();
+        assert_eq!(paths(plan), (vec!["/d".to_owned()], 24933642));
+
+        // 100000 bytes are needed: /a alone is not enough, but /a and /a/e can't both go.
+        let plan = plan_cleanup(&vfs, 48381165, 100000, &[]).unwrap();
+        assert_eq!(paths(plan), (vec!["/d".to_owned()], 24933642));
+
+        let plan = plan_cleanup(&vfs, 48381165, 90000, &[]).unwrap();
+        assert_eq!(paths(plan), (vec!["/a".to_owned()], 94853));
+
+        // Protecting /a/e keeps /a and / too.
+        let plan = plan_cleanup(&vfs, 48381165, 90000, &[path("/a/e")]).unwrap();
+        assert_eq!(paths(plan), (vec!["/d".to_owned()], 24933642));
+
+        let protected = [path("/a"), path("/d")];
+        assert_eq!(
+            plan_cleanup(&vfs, 48381165, 90000, &protected),
+            Err("Cannot free 90000 bytes".to_owned())
+        );
+
+        let plan = plan_cleanup(&vfs, 100000000, 100, &[]).unwrap();
+        assert_eq!(paths(plan), (vec![], 0));
+    }
+
+    #[test]
+    fn test_plan_cleanup_combines_directories() {
+        let (vfs, _) = interpret(
+            "\
+            $ ls
+            dir a
+            dir b
+            dir c
+            $ ls a
+            60 x
+            $ ls b
+            50 y
+            $ ls c
+            45 z"
+                .lines()
+                .map(|x| x.trim()),
+        );
+        // No single directory but / frees 100 bytes, but /a and /c together do.
+        let plan = plan_cleanup(&vfs, 155, 100, &[]).unwrap();
+        let mut paths = plan
+            .delete
+            .iter()
+            .map(|&id| vfs.path(id))
+            .collect::<Vec<_>>();
+        paths.sort();
+        assert_eq!(paths, ["/a", "/c"]);
+        assert_eq!(plan.freed, 105);
+    }
+
+    #[test]
+    fn test_plan_cleanup_large_disk() {
+        let (vfs, _) = interpret(
+            "\
+            $ ls
+            dir a
+            dir b
+            $ ls a
+            30000000000 x
+            $ ls b
+            20000000000 y"
+                .lines()
+                .map(|x| x.trim()),
+        );
+        let plan = plan_cleanup(&vfs, 60000000000, 15000000000, &[]).unwrap();
+        assert_eq!(plan.delete, [vfs.lookup("/b").unwrap()]);
+        assert_eq!(plan.freed, 20000000000);
+    }
+
+    #[test]
+    fn test_plan_cleanup_gives_up() {
+        // Every subset of these directories frees a different amount.
+        let mut transcript = "$ ls\n".to_owned();
+        for i in 0..22 {
+            transcript += &format!("dir d{i}\n");
+        }
+        for i in 0..22 {
+            transcript += &format!("$ ls d{i}\n{} f\n", 1usize << i);
+        }
+        let (vfs, _) = interpret(transcript.lines());
+        let total = vfs.size(ROOT);
+        let err = plan_cleanup(&vfs, total, total, &[]).unwrap_err();
+        assert!(err.starts_with("Too many ways"), "{err}");
+    }
+
+    #[test]
+    fn test_plan_cleanup_matches_brute_force() {
+        let transcripts = [
+            SAMPLE_INPUT,
+            "\
+            $ ls
+            dir a
+            dir b
+            dir c
+            $ ls a
+            60 x
+            $ ls b
+            50 y
+            $ ls c
+            45 z",
+            "\
+            $ ls
+            dir a
+            dir b
+            5 r
+            $ ls a
+            dir c
+            dir d
+            7 x
+            $ ls a/c
+            11 y
+            $ ls a/d
+            dir e
+            13 z
+            $ ls a/d/e
+            17 w
+            $ ls b
+            dir f
+            dir g
+            19 v
+            $ ls b/f
+            23 u
+            $ ls b/g
+            3 t",
+        ];
+        for transcript in transcripts {
+            let (vfs, _) = interpret(transcript.lines().map(|x| x.trim()));
+            let dirs = vfs.walk(ROOT).into_iter().map(|(id, _)| id);
+            let dirs = dirs.filter(|&id| vfs.is_dir(id)).collect::<Vec<_>>();
+            let total = vfs.size(ROOT);
+
+            // Whether `a` is `b` or one of its parents.
+            let nested = |a: NodeId, b: NodeId| {
+                let mut p = Some(b);
+                while let Some(id) = p {
+                    if id == a {
+                        return true;
+                    }
+                    p = vfs.parent(id);
+                }
+                false
+            };
+            let protections = [None].into_iter().chain(dirs.iter().copied().map(Some));
+            for protected in protections.map(|p| p.into_iter().collect::<Vec<_>>()) {
+                let candidates = dirs
+                    .iter()
+                    .copied()
+                    .filter(|&id| protected.iter().all(|&p| !nested(id, p) && !nested(p, id)))
+                    .collect::<Vec<_>>();
+                let mut reachable = BTreeSet::new();
+                for subset in 0..1usize << candidates.len() {
+                    let chosen = (0..candidates.len())
+                        .filter(|i| subset >> i & 1 == 1)
+                        .map(|i| candidates[i])
+                        .collect::<Vec<_>>();
+                    let antichain = chosen
+                        .iter()
+                        .all(|&a| chosen.iter().all(|&b| a == b || !nested(a, b)));
+                    if antichain {
+                        reachable.insert(chosen.iter().map(|&id| vfs.size(id)).sum::<usize>());
+                    }
+                }
+
+                for required in (0..=total + 1).step_by(total.div_ceil(40).max(1)) {
+                    let expected = reachable.range(required..).next().copied();
+                    let plan = plan_cleanup(&vfs, total, required, &protected);
+                    assert_eq!(plan.as_ref().ok().map(|p| p.freed), expected);
+                    if let Ok(plan) = plan {
+                        let sizes = plan.delete.iter().map(|&id| vfs.size(id));
+                        assert_eq!(sizes.sum::<usize>(), plan.freed);
+                        assert!(plan.delete.iter().all(|id| candidates.contains(id)));
+                        for &a in &plan.delete {
+                            assert!(plan.delete.iter().all(|&b| a == b || !nested(a, b)));
+                        }
+                    }
+                }
+            }
+        }
+    }
 }
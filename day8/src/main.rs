@@ -1,103 +1,177 @@
 use std::{
+    cmp::Reverse,
+    env,
     io::{self, BufRead},
-    iter::repeat,
     ops::Deref,
 };
 
 fn main() {
     let stdin = io::stdin();
     let lines = stdin.lock().lines().map(|x| x.unwrap());
-    let map = parse(lines);
-    println!("part1: {}", part1(&map));
-    println!("part2: {}", part2(&map));
+    let map = parse(lines).unwrap_or_else(|e| panic!("{e}"));
+
+    let mut args = env::args().skip(1);
+    match args.next().as_deref() {
+        None => {
+            println!("part1: {}", part1(&map));
+            println!("part2: {}", part2(&map));
+        }
+        Some("top") => {
+            let k = args.next().map_or(10, |k| k.parse().unwrap());
+            for (score, (row, col)) in top_scenic(&map, k) {
+                println!("row {row}, col {col}: {score}");
+            }
+        }
+        Some("heatmap") => print!("{}", heat_map(&map)),
+        Some(other) => panic!("Unknown command: {other}"),
+    }
 }
 
-fn parse(lines: impl Iterator<Item = impl Deref<Target = str>>) -> Vec<Vec<u32>> {
-    let mut result = vec![];
-    for line in lines {
-        let line: &str = &*line;
-        let mut line_vec = vec![];
-        for c in line.trim().chars() {
-            line_vec.push(c.to_digit(10).unwrap());
+/// Parses one row of trees per line. Heights are either single digits with nothing between
+/// them, or any numbers separated by whitespace or commas.
+fn parse(lines: impl Iterator<Item = impl Deref<Target = str>>) -> Result<Vec<Vec<u32>>, String> {
+    let mut result: Vec<Vec<u32>> = vec![];
+    for (i, line) in lines.enumerate() {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        let line_vec = if line.contains(|c: char| c == ',' || c.is_whitespace()) {
+            line.split(|c: char| c == ',' || c.is_whitespace())
+                .filter(|h| !h.is_empty())
+                .map(|h| {
+                    h.parse()
+                        .map_err(|_| format!("line {}: not a height: {h:?}", i + 1))
+                })
+                .collect::<Result<Vec<_>, _>>()?
+        } else {
+            line.chars()
+                .map(|c| {
+                    c.to_digit(10)
+                        .ok_or_else(|| format!("line {}: not a height: {c:?}", i + 1))
+                })
+                .collect::<Result<Vec<_>, _>>()?
+        };
+        if let Some(first) = result.first() {
+            if first.len() != line_vec.len() {
+                return Err(format!(
+                    "line {}: expected {} trees like the first row, got {}",
+                    i + 1,
+                    first.len(),
+                    line_vec.len()
+                ));
+            }
         }
         result.push(line_vec)
     }
-    result
+    Ok(result)
 }
 
-fn clone_false(map: &Vec<Vec<u32>>) -> Vec<Vec<bool>> {
-    map.iter()
-        .map(|x| x.iter().map(|_| false).collect::<Vec<_>>())
-        .collect::<Vec<_>>()
+/// Every straight line of sight through the forest, in all four directions, as the
+/// coordinates of the trees along it.
+fn lines_of_sight(map: &[Vec<u32>]) -> Vec<Vec<(usize, usize)>> {
+    let rows = map.len();
+    let cols = map.first().map_or(0, Vec::len);
+    let mut lines = vec![];
+    for x in 0..rows {
+        let line = (0..cols).map(|y| (x, y)).collect::<Vec<_>>();
+        lines.push(line.iter().rev().copied().collect());
+        lines.push(line);
+    }
+    for y in 0..cols {
+        let line = (0..rows).map(|x| (x, y)).collect::<Vec<_>>();
+        lines.push(line.iter().rev().copied().collect());
+        lines.push(line);
+    }
+    lines
 }
 
-fn part1(map: &Vec<Vec<u32>>) -> usize {
-    let mut visible = clone_false(map);
+/// For every tree, whether it is visible from outside the forest and its scenic score.
+///
+/// Each line of sight is swept once with a stack of the trees that are still in view, from
+/// tallest to shortest. A tree hides everything shorter than itself behind it, so when it
+/// is reached those are popped off; whatever remains on top is the tree that blocks its view.
+fn views(map: &[Vec<u32>]) -> (Vec<Vec<bool>>, Vec<Vec<usize>>) {
+    let mut visible = map
+        .iter()
+        .map(|line| vec![false; line.len()])
+        .collect::<Vec<_>>();
+    let mut scores = map
+        .iter()
+        .map(|line| vec![1; line.len()])
+        .collect::<Vec<_>>();
 
-    for (x, line) in map.iter().enumerate() {
-        for (y, &height) in line.iter().enumerate() {
-            visible[x][y] = is_visible(map, x, y, height)
+    for line in lines_of_sight(map) {
+        let mut stack: Vec<(u32, usize)> = vec![];
+        for (i, &(x, y)) in line.iter().enumerate() {
+            let height = map[x][y];
+            while stack.last().is_some_and(|&(h, _)| h < height) {
+                stack.pop();
+            }
+            visible[x][y] |= stack.is_empty();
+            scores[x][y] *= match stack.last() {
+                Some(&(_, blocker)) => i - blocker,
+                None => i,
+            };
+            stack.push((height, i));
         }
     }
+    (visible, scores)
+}
 
+fn part1(map: &[Vec<u32>]) -> usize {
+    let (visible, _) = views(map);
     visible.into_iter().flatten().filter(|&x| x).count()
 }
 
-fn is_visible(map: &[Vec<u32>], x: usize, y: usize, height: u32) -> bool {
-    let lines_of_sight: Vec<Box<dyn Iterator<Item = _>>> = vec![
-        Box::new((0..x).zip(repeat(y))),
-        Box::new((x + 1..map.len()).zip(repeat(y))),
-        Box::new(repeat(x).zip(0..y)),
-        Box::new(repeat(x).zip(y + 1..map.first().unwrap().len())),
-    ];
-
-    for range in lines_of_sight {
-        if range.map(|(x, y)| map[x][y]).all(|h| h < height) {
-            return true;
-        }
-    }
-    return false;
+fn part2(map: &[Vec<u32>]) -> usize {
+    let (_, scores) = views(map);
+    scores.into_iter().flatten().max().unwrap_or(0)
 }
 
-fn part2(map: &Vec<Vec<u32>>) -> usize {
-    let mut max = 0;
-
-    for (x, line) in map.iter().enumerate() {
-        for (y, &height) in line.iter().enumerate() {
-            max = usize::max(max, score(map, x, y, height));
-        }
-    }
-
-    max
+/// The `k` trees with the best scenic scores, best first, as `(score, (row, column))`.
+fn top_scenic(map: &[Vec<u32>], k: usize) -> Vec<(usize, (usize, usize))> {
+    let (_, scores) = views(map);
+    let mut trees = scores
+        .into_iter()
+        .enumerate()
+        .flat_map(|(row, line)| {
+            let trees = line.into_iter().enumerate();
+            trees.map(move |(col, score)| (score, (row, col)))
+        })
+        .collect::<Vec<_>>();
+    trees.sort_by_key(|&(score, (row, col))| (Reverse(score), row, col));
+    trees.truncate(k);
+    trees
 }
 
-fn score(map: &[Vec<u32>], x: usize, y: usize, height: u32) -> usize {
-    let lines_of_sight: Vec<Box<dyn Iterator<Item = _>>> = vec![
-        Box::new((0..x).rev().zip(repeat(y))),
-        Box::new((x + 1..map.len()).zip(repeat(y))),
-        Box::new(repeat(x).zip((0..y).rev())),
-        Box::new(repeat(x).zip(y + 1..map.first().unwrap().len())),
-    ];
-
-    eprint!("{y},{x} - {height}:");
-    let mut score = 1;
-    for range in lines_of_sight {
-        let mut dist = 0;
-        for (_i, h) in range.map(|(x, y)| map[x][y]).enumerate() {
-            dist += 1;
-            if h >= height {
-                break;
-            }
+/// Draws the scenic scores as shades from ` ` for the lowest to `@` for the highest.
+///
+/// Scores are products of four distances and so span several orders of magnitude; shading
+/// them logarithmically keeps the lower ones distinguishable.
+fn heat_map(map: &[Vec<u32>]) -> String {
+    const SHADES: &[u8] = b" .:-=+*#%@";
+    let (_, scores) = views(map);
+    let max = scores.iter().flatten().max().copied().unwrap_or(0);
+    let scale = ((max + 1) as f64).ln();
+    let mut result = String::new();
+    for line in scores {
+        for score in line {
+            let level = match max {
+                0 => 0.0,
+                _ => ((score + 1) as f64).ln() / scale,
+            };
+            result.push(SHADES[(level * (SHADES.len() - 1) as f64).round() as usize] as char);
         }
-        eprint!("{dist}");
-        score *= dist;
+        result.push('\n');
     }
-    eprintln!();
-    score
+    result
 }
 
 #[cfg(test)]
 mod test {
+    use std::iter::repeat;
+
     use super::*;
 
     static SAMPLE_INPUT: &str = "\
@@ -111,15 +185,121 @@ mod test {
         SAMPLE_INPUT.lines().map(|x| x.trim())
     }
 
+    // The original implementations, which walk every line of sight from every tree.
+
+    fn is_visible(map: &[Vec<u32>], x: usize, y: usize, height: u32) -> bool {
+        let lines_of_sight: Vec<Box<dyn Iterator<Item = _>>> = vec![
+            Box::new((0..x).zip(repeat(y))),
+            Box::new((x + 1..map.len()).zip(repeat(y))),
+            Box::new(repeat(x).zip(0..y)),
+            Box::new(repeat(x).zip(y + 1..map.first().unwrap().len())),
+        ];
+
+        for range in lines_of_sight {
+            if range.map(|(x, y)| map[x][y]).all(|h| h < height) {
+                return true;
+            }
+        }
+        false
+    }
+
+    fn score(map: &[Vec<u32>], x: usize, y: usize, height: u32) -> usize {
+        let lines_of_sight: Vec<Box<dyn Iterator<Item = _>>> = vec![
+            Box::new((0..x).rev().zip(repeat(y))),
+            Box::new((x + 1..map.len()).zip(repeat(y))),
+            Box::new(repeat(x).zip((0..y).rev())),
+            Box::new(repeat(x).zip(y + 1..map.first().unwrap().len())),
+        ];
+
+        let mut score = 1;
+        for range in lines_of_sight {
+            let mut dist = 0;
+            for h in range.map(|(x, y)| map[x][y]) {
+                dist += 1;
+                if h >= height {
+                    break;
+                }
+            }
+            score *= dist;
+        }
+        score
+    }
+
     #[test]
     fn test_part1() {
-        let root = parse(sample_lines());
+        let root = parse(sample_lines()).unwrap();
         assert_eq!(part1(&root), 21);
     }
 
     #[test]
     fn test_part2() {
-        let root = parse(sample_lines());
+        let root = parse(sample_lines()).unwrap();
         assert_eq!(part2(&root), 8);
     }
+
+    #[test]
+    fn test_views_match_naive() {
+        let maps = [
+            SAMPLE_INPUT,
+            "7",
+            "3131",
+            "1\n2\n2\n1",
+            "555\n555",
+            "0123\n1234\n2345",
+            "9000\n0900\n0090\n0009\n1111",
+            "2515\n5151\n1525\n4441\n0303\n3030",
+        ];
+        for map in maps {
+            let map = parse(map.lines()).unwrap();
+            let (visible, scores) = views(&map);
+            for (x, line) in map.iter().enumerate() {
+                for (y, &height) in line.iter().enumerate() {
+                    assert_eq!(visible[x][y], is_visible(&map, x, y, height), "{map:?}");
+                    assert_eq!(scores[x][y], score(&map, x, y, height), "{map:?}");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_parse() {
+        let digits = parse(sample_lines()).unwrap();
+        let separated = parse(
+            [
+                "3 0 3 7 3",
+                "2,5,5,1,2",
+                "6, 5, 3, 3, 2",
+                "3\t3 5 4 9",
+                "3 5 3 9 0",
+            ]
+            .into_iter(),
+        );
+        assert_eq!(separated, Ok(digits));
+
+        let tall = parse(["10 200 3", "4 5 6"].into_iter()).unwrap();
+        assert_eq!(tall, [[10, 200, 3], [4, 5, 6]]);
+        assert_eq!(part1(&tall), 6);
+
+        assert_eq!(
+            parse(["123", "45"].into_iter()),
+            Err("line 2: expected 3 trees like the first row, got 2".to_owned())
+        );
+        assert!(parse(["12a"].into_iter()).is_err());
+        assert!(parse(["1 2 -3"].into_iter()).is_err());
+    }
+
+    #[test]
+    fn test_top_scenic() {
+        let map = parse(sample_lines()).unwrap();
+        assert_eq!(top_scenic(&map, 3), [(8, (3, 2)), (6, (2, 1)), (4, (1, 2))]);
+
+        let wide = parse(["1 2 3 4 5 6 7", "7 6 5 4 3 2 1"].into_iter()).unwrap();
+        assert_eq!(top_scenic(&wide, 1), [(0, (0, 0))]);
+    }
+
+    #[test]
+    fn test_heat_map() {
+        let map = parse(sample_lines()).unwrap();
+        assert_eq!(heat_map(&map), "     \n -#- \n %-+ \n -@* \n     \n");
+    }
 }
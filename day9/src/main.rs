@@ -1,76 +1,166 @@
 use std::{
-    collections::{HashSet},
-    io::{self, BufRead},
+    collections::HashSet,
+    env,
+    io::{self, BufRead, Write},
     ops::Deref,
+    process,
 };
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 enum Dir {
     R,
     U,
     L,
     D,
+    UR,
+    UL,
+    DR,
+    DL,
 }
 
 impl Dir {
-    fn of(c: char) -> Dir {
-        match c {
-            'R' => Dir::R,
-            'L' => Dir::L,
-            'U' => Dir::U,
-            'D' => Dir::D,
-            _ => panic!("bad dir: {c}"),
+    fn of(s: &str) -> Dir {
+        match s {
+            "R" => Dir::R,
+            "L" => Dir::L,
+            "U" => Dir::U,
+            "D" => Dir::D,
+            "UR" | "RU" => Dir::UR,
+            "UL" | "LU" => Dir::UL,
+            "DR" | "RD" => Dir::DR,
+            "DL" | "LD" => Dir::DL,
+            _ => panic!("bad dir: {s}"),
         }
     }
-    fn step(self, (x, y): (i32, i32)) -> (i32, i32) {
+    fn delta(self) -> Pos {
         match self {
-            Dir::R => (x + 1, y),
-            Dir::U => (x, y + 1),
-            Dir::L => (x - 1, y),
-            Dir::D => (x, y - 1),
+            Dir::R => (1, 0),
+            Dir::U => (0, 1),
+            Dir::L => (-1, 0),
+            Dir::D => (0, -1),
+            Dir::UR => (1, 1),
+            Dir::UL => (-1, 1),
+            Dir::DR => (1, -1),
+            Dir::DL => (-1, -1),
         }
     }
+    fn step(self, (x, y): Pos) -> Pos {
+        let (dx, dy) = self.delta();
+        (x + dx, y + dy)
+    }
 }
 
+type Pos = (i32, i32);
 type Parsed = Vec<(Dir, usize)>;
 
 fn main() {
     let stdin = io::stdin();
     let lines = stdin.lock().lines().map(|x| x.unwrap());
     let map = parse(lines);
-    println!("part1: {}", part1(&map));
-    println!("part2: {}", part2(&map));
+
+    let mut args = env::args().skip(1);
+    match args.next().as_deref() {
+        None => {
+            println!("part1: {}", part1(&map));
+            println!("part2: {}", part2(&map));
+        }
+        Some(mode @ ("frames" | "svg")) => {
+            let knots = args.next().map_or(2, |k| knot_count(&k));
+            if mode == "frames" {
+                for frame in frames(&map, knots) {
+                    println!("{frame}");
+                }
+            } else {
+                svg(&map, knots, &mut io::stdout().lock()).unwrap();
+            }
+        }
+        Some(knots) => {
+            let knots = knot_count(knots);
+            let knot = args.next().map_or(knots - 1, |k| k.parse().unwrap());
+            println!(
+                "knot {knot} of {knots}: {}",
+                visited(&map, knots, knot).len()
+            );
+        }
+    }
+}
+
+/// Reads a knot count from the command line, exiting with a message unless it is at least 1.
+fn knot_count(arg: &str) -> usize {
+    match arg.parse() {
+        Ok(knots) if knots > 0 => knots,
+        _ => {
+            eprintln!("Expected a number of knots of at least 1, got {arg:?}");
+            process::exit(2);
+        }
+    }
 }
 
 fn parse(lines: impl Iterator<Item = impl Deref<Target = str>>) -> Parsed {
     lines
         .map(|line| {
-            let line: &str = &*line;
+            let line: &str = &line;
             let (d, c) = line.split_once(' ').unwrap();
-            (
-                Dir::of(d.chars().next().unwrap()),
-                c.parse::<usize>().unwrap(),
-            )
+            (Dir::of(d), c.parse::<usize>().unwrap())
         })
         .collect::<Parsed>()
 }
 
-fn part1(parsed: &Parsed) -> usize {
-    let mut head = (0, 0);
-    let mut tail = (0, 0);
-    let mut visited: HashSet<(i32, i32)> = HashSet::new();
-    visited.insert(tail);
-    for &(d, c) in parsed {
-        for _ in 0..c {
-            head = d.step(head);
-            tail = clamp(tail, head);
-            visited.insert(tail);
+/// A rope of knots starting on top of each other, the first of which is the head.
+#[derive(Debug, Clone)]
+struct Rope {
+    knots: Vec<Pos>,
+}
+
+impl Rope {
+    fn new(len: usize) -> Rope {
+        assert!(len > 0, "a rope needs at least one knot");
+        Rope {
+            knots: vec![(0, 0); len],
+        }
+    }
+
+    fn knots(&self) -> &[Pos] {
+        &self.knots
+    }
+
+    /// Moves the head one step and lets every other knot follow the one before it.
+    fn step(&mut self, d: Dir) {
+        self.knots[0] = d.step(self.knots[0]);
+        for i in 1..self.knots.len() {
+            self.knots[i] = clamp(self.knots[i], self.knots[i - 1]);
         }
     }
-    visited.len()
+
+    /// The positions of all knots after every single step of `moves`, starting with the
+    /// initial position.
+    fn steps(mut self, moves: &Parsed) -> impl Iterator<Item = Vec<Pos>> + '_ {
+        let start = self.knots.clone();
+        let steps = moves
+            .iter()
+            .flat_map(|&(d, c)| (0..c).map(move |_| d))
+            .map(move |d| {
+                self.step(d);
+                self.knots().to_vec()
+            });
+        Some(start).into_iter().chain(steps)
+    }
+}
+
+/// The cells visited by knot `knot` of a rope with `knots` knots.
+fn visited(moves: &Parsed, knots: usize, knot: usize) -> HashSet<Pos> {
+    assert!(knot < knots, "knot {knot} is not on a rope of {knots}");
+    Rope::new(knots)
+        .steps(moves)
+        .map(|positions| positions[knot])
+        .collect()
 }
 
-fn clamp(tail: (i32, i32), head: (i32, i32)) -> (i32, i32) {
+fn part1(parsed: &Parsed) -> usize {
+    visited(parsed, 2, 1).len()
+}
+
+fn clamp(tail: Pos, head: Pos) -> Pos {
     let dx = head.0 - tail.0;
     let dy = head.1 - tail.1;
     if dx.abs() > 1 || dy.abs() > 1 {
@@ -81,20 +171,130 @@ fn clamp(tail: (i32, i32), head: (i32, i32)) -> (i32, i32) {
 }
 
 fn part2(parsed: &Parsed) -> usize {
-    let mut knots = [(0, 0); 10];
-    let mut visited: HashSet<(i32, i32)> = HashSet::new();
-    for &(d, c) in parsed {
-        for _ in 0..c {
-            knots[0] = d.step(knots[0]);
-            let mut prev = knots[0];
-            for k in &mut knots[1..] {
-                *k = clamp(*k, prev);
-                prev = *k;
+    visited(parsed, 10, 9).len()
+}
+
+/// The smallest and largest coordinates any knot reaches.
+fn bounds(states: impl Iterator<Item = Vec<Pos>>) -> (Pos, Pos) {
+    states
+        .flatten()
+        .fold(((0, 0), (0, 0)), |(min, max), (x, y)| {
+            ((min.0.min(x), min.1.min(y)), (max.0.max(x), max.1.max(y)))
+        })
+}
+
+/// `H` for the head, then `1`-`9` and `a`-`z`; knots beyond that are `*`.
+fn knot_label(i: usize) -> char {
+    match i {
+        0 => 'H',
+        _ => char::from_digit(i as u32, 36).unwrap_or('*'),
+    }
+}
+
+/// Draws one state of the rope in the puzzle's notation, with up at the top. A knot
+/// covers the ones behind it, all of them cover the start `s`, which covers the cells
+/// the tail visited `#`.
+fn frame(knots: &[Pos], visited: &HashSet<Pos>, (min, max): (Pos, Pos)) -> String {
+    let mut result = String::new();
+    for y in (min.1..=max.1).rev() {
+        for x in min.0..=max.0 {
+            let c = match knots.iter().position(|&k| k == (x, y)) {
+                Some(i) => knot_label(i),
+                None if (x, y) == (0, 0) => 's',
+                None if visited.contains(&(x, y)) => '#',
+                None => '.',
+            };
+            result.push(c);
+        }
+        result.push('\n');
+    }
+    result
+}
+
+/// One frame for the start and every step of `moves` on a rope of `knots` knots, all
+/// drawn over the same area. Each frame is only drawn once the iterator gets to it.
+fn frames(moves: &Parsed, knots: usize) -> impl Iterator<Item = String> + '_ {
+    let bounds = bounds(Rope::new(knots).steps(moves));
+    let mut visited = HashSet::new();
+    Rope::new(knots).steps(moves).map(move |state| {
+        visited.insert(*state.last().unwrap());
+        frame(&state, &visited, bounds)
+    })
+}
+
+/// Writes an animated SVG of the rope: a cell lights up once the tail visits it, and the
+/// knots jump between positions on every step. The faint line is the path of the head.
+///
+/// The rope is simulated again for each part of the drawing rather than keeping every
+/// state around, so only the cells the tail visited are held in memory.
+fn svg(moves: &Parsed, knots: usize, out: &mut impl Write) -> io::Result<()> {
+    const CELL: i32 = 10;
+    const STEP_SECONDS: f64 = 0.1;
+    let states = || Rope::new(knots).steps(moves);
+    let ((min_x, min_y), (max_x, max_y)) = bounds(states());
+    // SVG's y axis points down.
+    let to_svg = |(x, y): Pos| ((x - min_x) * CELL, (max_y - y) * CELL);
+    let centre = |p: Pos| {
+        let (x, y) = to_svg(p);
+        (x + CELL / 2, y + CELL / 2)
+    };
+    let duration = states().count() as f64 * STEP_SECONDS;
+
+    let (width, height) = ((max_x - min_x + 1) * CELL, (max_y - min_y + 1) * CELL);
+    writeln!(
+        out,
+        r#"<svg xmlns="http://www.w3.org/2000/svg" viewBox="0 0 {width} {height}" width="{width}" height="{height}">"#
+    )?;
+    writeln!(out, r##"<rect width="100%" height="100%" fill="#fff"/>"##)?;
+
+    let mut seen = HashSet::new();
+    for (t, state) in states().enumerate() {
+        let tail = *state.last().unwrap();
+        if seen.insert(tail) {
+            let (x, y) = to_svg(tail);
+            let begin = t as f64 * STEP_SECONDS;
+            writeln!(
+                out,
+                r##"<rect x="{x}" y="{y}" width="{CELL}" height="{CELL}" fill="#ccc" visibility="hidden"><set attributeName="visibility" to="visible" begin="{begin:.1}s" fill="freeze"/></rect>"##
+            )?;
+        }
+    }
+
+    write!(out, r#"<polyline points=""#)?;
+    for (t, state) in states().enumerate() {
+        let (x, y) = centre(state[0]);
+        write!(out, "{}{x},{y}", if t == 0 { "" } else { " " })?;
+    }
+    writeln!(
+        out,
+        r##"" fill="none" stroke="#f00" stroke-opacity="0.3"/>"##
+    )?;
+
+    // Drawn tail first so that the head ends up on top.
+    for knot in (0..knots).rev() {
+        let (cx, cy) = centre((0, 0));
+        let fill = if knot == 0 { "#d00" } else { "#04a" };
+        writeln!(
+            out,
+            r#"<circle cx="{cx}" cy="{cy}" r="{}" fill="{fill}"><title>{}</title>"#,
+            CELL / 2 - 1,
+            knot_label(knot)
+        )?;
+        for attribute in ["cx", "cy"] {
+            write!(out, r#"<animate attributeName="{attribute}" values=""#)?;
+            for (t, state) in states().enumerate() {
+                let (x, y) = centre(state[knot]);
+                let value = if attribute == "cx" { x } else { y };
+                write!(out, "{}{value}", if t == 0 { "" } else { ";" })?;
             }
-            visited.insert(prev);
+            writeln!(
+                out,
+                r#"" dur="{duration:.1}s" calcMode="discrete" fill="freeze"/>"#
+            )?;
         }
+        writeln!(out, "</circle>")?;
     }
-    visited.len()
+    writeln!(out, "</svg>")
 }
 
 #[cfg(test)]
@@ -146,4 +346,55 @@ mod test {
         let root = parse(sample_lines2());
         assert_eq!(part2(&root), 36);
     }
+
+    #[test]
+    fn test_diagonal() {
+        let moves = parse(["UR 3", "DL 1", "R 2"].into_iter());
+        assert_eq!(moves[0], (Dir::UR, 3));
+        assert_eq!(
+            visited(&moves, 2, 1),
+            HashSet::from([(0, 0), (1, 1), (2, 2), (3, 2)])
+        );
+        assert_eq!(visited(&moves, 2, 0).len(), 6);
+    }
+
+    #[test]
+    fn test_steps() {
+        let moves = parse(sample_lines());
+        let states = Rope::new(3).steps(&moves).collect::<Vec<_>>();
+        assert_eq!(states.len(), 1 + 24);
+        assert_eq!(states[0], [(0, 0); 3]);
+        assert_eq!(states[4], [(4, 0), (3, 0), (2, 0)]);
+        assert_eq!(states[8], [(4, 4), (4, 3), (4, 2)]);
+        assert_eq!(visited(&moves, 10, 1).len(), part1(&moves));
+        assert_eq!(visited(&moves, 1, 0).len(), visited(&moves, 5, 0).len());
+    }
+
+    #[test]
+    fn test_frames() {
+        let moves = parse(sample_lines());
+        let frames = frames(&moves, 2).collect::<Vec<_>>();
+        assert_eq!(frames.len(), 25);
+        assert_eq!(frames[0], "......\n......\n......\n......\nH.....\n");
+        assert_eq!(frames[1], "......\n......\n......\n......\n1H....\n");
+        assert_eq!(frames[24], "..##..\n...##.\n.1H##.\n....#.\ns###..\n");
+
+        let long = frame(&Rope::new(12).knots, &HashSet::new(), ((0, 0), (1, 0)));
+        assert_eq!(long, "H.\n");
+        assert_eq!(knot_label(10), 'a');
+        assert_eq!(knot_label(40), '*');
+    }
+
+    #[test]
+    fn test_svg() {
+        let moves = parse(sample_lines());
+        let mut svg = Vec::new();
+        super::svg(&moves, 2, &mut svg).unwrap();
+        let svg = String::from_utf8(svg).unwrap();
+        assert!(svg.starts_with(r#"<svg xmlns="http://www.w3.org/2000/svg" viewBox="0 0 60 50""#));
+        assert!(svg.ends_with("</svg>\n"));
+        assert_eq!(svg.matches("<set ").count(), part1(&moves));
+        assert_eq!(svg.matches("<circle ").count(), 2);
+        assert_eq!(svg.matches("<animate ").count(), 4);
+    }
 }